@@ -63,26 +63,31 @@ impl Contract {
     }
 
     /// This must be called when proposal to payout bounty has been voted either successfully or not.
+    /// On success, returns the transfer that pays out the bounty.
     pub(crate) fn internal_execute_bounty_payout(
         &mut self,
         id: u64,
         receiver_id: &AccountId,
         success: bool,
-    ) -> PromiseOrValue<()> {
+    ) -> Option<Instruction> {
         let mut bounty: Bounty = self.bounties.get(&id).expect("ERR_NO_BOUNTY").into();
         let (claims, claim_idx) = self.internal_get_claims(id, &receiver_id);
         self.internal_remove_claim(id, claims, claim_idx);
         if success {
-            let res = self.internal_payout(&bounty.token, receiver_id, bounty.amount.0);
+            let res = Instruction::Transfer {
+                token_id: bounty.token.clone(),
+                receiver_id: receiver_id.clone(),
+                amount: bounty.amount,
+            };
             if bounty.times == 0 {
                 self.bounties.remove(&id);
             } else {
                 bounty.times -= 1;
                 self.bounties.insert(&id, &VersionedBounty::Default(bounty));
             }
-            res
+            Some(res)
         } else {
-            PromiseOrValue::Value(())
+            None
         }
     }
 
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+
+use near_sdk::json_types::{Base58CryptoHash, U128};
+use near_sdk::{env, near_bindgen, AccountId, CryptoHash};
+
+use crate::policy::UnrevealedVotes;
+use crate::proposals::{Proposal, ProposalPhase, ProposalStatus, VersionedProposal, VoteChoice};
+use crate::types::Action;
+use crate::*;
+
+/// Returns the hash a voter commits to for a secret vote with the given choice, ballot,
+/// amounts and salt, which is the hash of `"<account id>:<choice>:<versions>:<amounts>:<salt>"`.
+/// The choice is `reject`, `abstain` or the approved version, the versions are separated
+/// by commas and the amounts are `<version>=<amount>` pairs ordered by version and
+/// separated by commas.
+pub fn vote_hash(
+    account_id: &AccountId,
+    choice: VoteChoice,
+    versions: &[u8],
+    amounts: &HashMap<u8, U128>,
+    salt: &str,
+) -> Base58CryptoHash {
+    let choice = match choice {
+        VoteChoice::Reject => "reject".to_string(),
+        VoteChoice::Approve(version) => version.to_string(),
+        VoteChoice::Abstain => "abstain".to_string(),
+    };
+    let versions: Vec<String> = versions.iter().map(|v| v.to_string()).collect();
+    let mut amounts: Vec<(&u8, &U128)> = amounts.iter().collect();
+    amounts.sort_unstable_by_key(|(version, _)| **version);
+    let amounts: Vec<String> = amounts
+        .into_iter()
+        .map(|(version, amount)| format!("{}={}", version, amount.0))
+        .collect();
+    let mut hash = CryptoHash::default();
+    hash.copy_from_slice(&env::sha256(
+        format!(
+            "{}:{}:{}:{}:{}",
+            account_id,
+            choice,
+            versions.join(","),
+            amounts.join(","),
+            salt
+        )
+        .as_bytes(),
+    ));
+    hash.into()
+}
+
+impl Contract {
+    /// Counts the commitments that were never revealed according to the vote policy.
+    pub(crate) fn internal_tally_unrevealed(&self, policy: &Policy, proposal: &mut Proposal) {
+        let vote_policy = policy
+            .get_vote_policy(&proposal.kind)
+            .unwrap_or(&policy.default_vote_policy);
+        let secret_ballot = match &vote_policy.secret_ballot {
+            Some(secret_ballot) => secret_ballot,
+            None => return,
+        };
+        let commitments: Vec<AccountId> = proposal.commitments.drain().map(|(a, _)| a).collect();
+        if let UnrevealedVotes::Ignore = secret_ballot.unrevealed {
+            return;
+        }
+        let total_weight = policy.get_total_weight(
+            vote_policy,
+            self.internal_total_supply(policy, vote_policy, proposal.submission_time.0),
+            &proposal.kind,
+        );
+        for account_id in commitments {
+            let vote = proposal.create_vote(
+                vote_policy,
+                VoteChoice::Abstain,
+                vec![],
+                HashMap::new(),
+                self.internal_vote_power(policy, vote_policy, &account_id, proposal.submission_time.0),
+            );
+            // abstentions don't count in the houses of bicameral votes
+            proposal.update_votes(&account_id, vote, vote_policy, total_weight, &[]);
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Commit to a secret vote on a proposal with its `vote_hash`. Under ranked and
+    /// approval ballots the versions are the ranking or the approved versions, and the
+    /// choice approves the first of them. Committing again replaces the previous commitment.
+    pub fn commit_vote(&mut self, id: u64, hash: Base58CryptoHash) {
+        let mut proposal: Proposal = self.proposals.get(&id).expect("ERR_NO_PROPOSAL").into();
+        let policy = self.policy.get().unwrap().to_policy();
+        let allowed = [Action::VoteApprove, Action::VoteReject, Action::VoteAbstain]
+            .iter()
+            .any(|action| {
+                policy.can_execute_action(self.internal_user_info(), &proposal.kind, action)
+            });
+        assert!(allowed, "ERR_PERMISSION_DENIED");
+        assert_eq!(
+            proposal.status,
+            ProposalStatus::InProgress,
+            "ERR_PROPOSAL_NOT_IN_PROGRESS"
+        );
+        let vote_policy = policy
+            .get_vote_policy(&proposal.kind)
+            .unwrap_or(&policy.default_vote_policy);
+        assert!(vote_policy.secret_ballot.is_some(), "ERR_NOT_SECRET_BALLOT");
+        assert_eq!(
+            policy.proposal_phase(&proposal),
+            ProposalPhase::Voting,
+            "ERR_NOT_VOTING_PHASE"
+        );
+        proposal
+            .commitments
+            .insert(env::predecessor_account_id(), hash);
+        self.proposals
+            .insert(&id, &VersionedProposal::Default(proposal));
+    }
+
+    /// Reveal the vote committed to by the caller. Only possible in the reveal period.
+    /// The vote is tallied with the ballot kind of the vote policy.
+    pub fn reveal_vote(
+        &mut self,
+        id: u64,
+        choice: VoteChoice,
+        versions: Vec<u8>,
+        amounts: HashMap<u8, U128>,
+        salt: String,
+    ) {
+        let proposal: Proposal = self.proposals.get(&id).expect("ERR_NO_PROPOSAL").into();
+        let policy = self.policy.get().unwrap().to_policy();
+        let ballot_kind = if versions.is_empty() {
+            BallotKind::Single
+        } else {
+            assert_eq!(choice, VoteChoice::Approve(versions[0]), "ERR_INVALID_CHOICE");
+            policy
+                .get_vote_policy(&proposal.kind)
+                .unwrap_or(&policy.default_vote_policy)
+                .ballot_kind
+                .clone()
+        };
+        self.handle_vote(id, choice, ballot_kind, versions, amounts, Some(salt))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::json_types::WrappedDuration;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    use crate::policy::SecretBallot;
+    use crate::test_utils::*;
+
+    use super::*;
+
+    fn secret_ballot_contract(context: &mut VMContextBuilder, ballot_kind: BallotKind) -> Contract {
+        let mut policy = VersionedPolicy::Default(vec![
+            accounts(1).into(),
+            accounts(2).into(),
+            accounts(3).into(),
+        ])
+        .upgrade();
+        policy.to_policy_mut().proposal_period = WrappedDuration::from(100);
+        policy.to_policy_mut().default_vote_policy.ballot_kind = ballot_kind;
+        policy.to_policy_mut().default_vote_policy.secret_ballot = Some(SecretBallot {
+            reveal_period: WrappedDuration::from(100),
+            unrevealed: UnrevealedVotes::Abstain,
+        });
+        new_contract(context, policy)
+    }
+
+    #[test]
+    fn test_secret_ballot() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = secret_ballot_contract(&mut context, BallotKind::Single);
+        let id = create_proposal(&mut context, &mut contract);
+        let choices = [
+            (1, VoteChoice::Approve(0)),
+            (2, VoteChoice::Approve(0)),
+            (3, VoteChoice::Reject),
+        ];
+        for (i, choice) in choices.iter() {
+            testing_env!(context.predecessor_account_id(accounts(*i)).build());
+            let salt = format!("salt{}", i);
+            contract.commit_vote(id, vote_hash(&accounts(*i).into(), *choice, &[], &HashMap::new(), &salt));
+        }
+        assert!(contract.get_proposal(id).proposal.votes.is_empty());
+
+        testing_env!(context.block_timestamp(150).build());
+        assert_eq!(contract.get_proposal_phase(id), ProposalPhase::Reveal);
+        for i in 1..3 {
+            testing_env!(context.predecessor_account_id(accounts(i)).build());
+            contract.reveal_vote(id, VoteChoice::Approve(0), vec![], HashMap::new(), format!("salt{}", i));
+        }
+        // revealed votes are only tallied once the reveal period is over.
+        let proposal = contract.get_proposal(id).proposal;
+        assert_eq!(proposal.approve_count[0], 2);
+        assert_eq!(proposal.status, ProposalStatus::InProgress);
+
+        testing_env!(context.block_timestamp(250).build());
+        contract.finalize(id);
+        let proposal = contract.get_proposal(id).proposal;
+        assert_eq!(proposal.abstain_count, 1);
+        assert_eq!(proposal.status, ProposalStatus::Approved{ version: 0 });
+    }
+
+    #[test]
+    fn test_secret_approval_ballot() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = secret_ballot_contract(&mut context, BallotKind::Approval);
+        let id = create_proposal(&mut context, &mut contract);
+        contract.counter_propose(id, "counter".to_string(), transfer_to(accounts(3).into()));
+        let ballots: [(usize, Vec<u8>); 2] = [(2, vec![0, 1]), (3, vec![1])];
+        for (i, versions) in ballots.iter() {
+            testing_env!(context.predecessor_account_id(accounts(*i)).build());
+            let choice = VoteChoice::Approve(versions[0]);
+            let hash = vote_hash(&accounts(*i).into(), choice, versions, &HashMap::new(), "salt");
+            contract.commit_vote(id, hash);
+        }
+        assert!(contract.get_proposal(id).proposal.votes.is_empty());
+
+        // the ballots are revealed and tallied as approval ballots.
+        testing_env!(context.block_timestamp(150).build());
+        for (i, versions) in ballots.iter() {
+            testing_env!(context.predecessor_account_id(accounts(*i)).build());
+            let choice = VoteChoice::Approve(versions[0]);
+            contract.reveal_vote(id, choice, versions.clone(), HashMap::new(), "salt".to_string());
+        }
+        assert_eq!(contract.get_proposal(id).proposal.approve_count, vec![1, 2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_SECRET_BALLOT")]
+    fn test_secret_ballot_direct_vote() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = secret_ballot_contract(&mut context, BallotKind::Single);
+        let id = create_proposal(&mut context, &mut contract);
+        contract.approve(id, 0);
+    }
+}
@@ -0,0 +1,389 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{WrappedTimestamp, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId, Balance};
+
+use crate::policy::ConvictionPolicy;
+use crate::proposals::{Instruction, Proposal, ProposalPhase, ProposalStatus, VersionedProposal};
+use crate::types::{Action, BASE_TOKEN};
+use crate::*;
+
+/// Tokens staked by a member behind a version of a conviction voted proposal.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ConvictionStake {
+    /// Version of the proposal that is backed.
+    pub version: u8,
+    /// Amount of delegated tokens staked.
+    pub amount: U128,
+    /// Time the stake was last changed.
+    pub since: WrappedTimestamp,
+}
+
+/// Conviction of a version of a conviction voted proposal, as of its last update.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Conviction {
+    /// Amount of delegated tokens staked behind the version.
+    pub staked: U128,
+    /// Conviction built up by the time of the update.
+    pub conviction: U128,
+    /// Time of the update.
+    pub updated: WrappedTimestamp,
+}
+
+impl Conviction {
+    pub fn new(now: u64) -> Self {
+        Conviction {
+            staked: U128(0),
+            conviction: U128(0),
+            updated: WrappedTimestamp::from(now),
+        }
+    }
+
+    /// Returns the conviction at the given time.
+    pub fn at(&self, conviction_policy: &ConvictionPolicy, now: u64) -> Balance {
+        conviction_policy.conviction(
+            self.conviction.0,
+            self.staked.0,
+            now.saturating_sub(self.updated.0),
+        )
+    }
+
+    /// Records a change of the amount staked behind the version at the given time.
+    fn update(&mut self, conviction_policy: &ConvictionPolicy, now: u64, staked: Balance) {
+        self.conviction = U128(self.at(conviction_policy, now));
+        self.staked = U128(staked);
+        self.updated = WrappedTimestamp::from(now);
+    }
+}
+
+/// This is format of output via JSON for the conviction of a proposal version.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ConvictionOutput {
+    /// Conviction built up at the moment.
+    pub conviction: U128,
+    /// Conviction needed to pass. Empty if the version requests more than the spending limit.
+    pub threshold: Option<U128>,
+}
+
+impl Proposal {
+    /// Returns the conviction each version has built up at the given time.
+    pub fn conviction(&self, conviction_policy: &ConvictionPolicy, now: u64) -> Vec<Balance> {
+        self.convictions
+            .iter()
+            .map(|conviction| conviction.at(conviction_policy, now))
+            .collect()
+    }
+
+    /// Adds `amount` to the tokens staked behind the given version, or takes it out.
+    fn update_conviction(
+        &mut self,
+        conviction_policy: &ConvictionPolicy,
+        version: u8,
+        amount: Balance,
+        add: bool,
+    ) {
+        let conviction = &mut self.convictions[version as usize];
+        let staked = if add {
+            conviction.staked.0 + amount
+        } else {
+            conviction.staked.0 - amount
+        };
+        conviction.update(conviction_policy, env::block_timestamp(), staked);
+    }
+}
+
+/// Returns the amount of $NEAR a set of instructions requests from the treasury.
+fn requested_amount(instructions: &[Instruction]) -> Balance {
+    instructions
+        .iter()
+        .map(|instr| match instr {
+            Instruction::Transfer { token_id, amount, .. } if token_id == BASE_TOKEN => amount.0,
+            Instruction::CreateVesting { vesting } => vesting.total.0,
+            _ => 0,
+        })
+        .sum()
+}
+
+impl Contract {
+    /// Conviction is measured against the $NEAR treasury, so only $NEAR can be requested.
+    pub(crate) fn internal_check_conviction_instructions(&self, instructions: &[Instruction]) {
+        for instr in instructions {
+            match instr {
+                Instruction::Transfer { token_id, .. } => {
+                    assert_eq!(token_id, BASE_TOKEN, "ERR_CONVICTION_ONLY_BASE_TOKEN")
+                }
+                Instruction::CreateVesting { vesting } => {
+                    assert_eq!(vesting.token_id, BASE_TOKEN, "ERR_CONVICTION_ONLY_BASE_TOKEN")
+                }
+                _ => {}
+            }
+            assert!(
+                !matches!(instr, Instruction::CreateRecurringPayment { .. }),
+                "ERR_CONVICTION_NO_RECURRING_PAYMENT"
+            );
+        }
+    }
+
+    /// Returns the conviction and threshold of each version of the given proposal.
+    pub(crate) fn internal_conviction(
+        &self,
+        conviction_policy: &ConvictionPolicy,
+        proposal: &Proposal,
+    ) -> Vec<(Balance, Option<Balance>)> {
+        let available = self.internal_available_amount();
+        proposal
+            .conviction(conviction_policy, env::block_timestamp())
+            .into_iter()
+            .zip(proposal.versions.iter())
+            .map(|(conviction, version)| {
+                let threshold = conviction_policy.threshold(
+                    requested_amount(&version.instructions),
+                    available,
+                    self.total_delegation_amount,
+                );
+                (conviction, threshold)
+            })
+            .collect()
+    }
+
+    /// Returns the status of a conviction voted proposal.
+    pub(crate) fn internal_conviction_status(
+        &self,
+        conviction_policy: &ConvictionPolicy,
+        proposal: &Proposal,
+    ) -> ProposalStatus {
+        for (version, (conviction, threshold)) in self
+            .internal_conviction(conviction_policy, proposal)
+            .into_iter()
+            .enumerate()
+        {
+            if proposal.remove_flag[version] {
+                continue;
+            }
+            if let Some(threshold) = threshold {
+                if conviction >= threshold {
+                    return ProposalStatus::Approved { version: version as u8 };
+                }
+            }
+        }
+        ProposalStatus::InProgress
+    }
+
+    /// Approves the given conviction voted proposal if one of its versions passed,
+    /// and stores it.
+    fn internal_check_conviction(
+        &mut self,
+        id: u64,
+        policy: &Policy,
+        conviction_policy: &ConvictionPolicy,
+        mut proposal: Proposal,
+    ) {
+        proposal.status = self.internal_conviction_status(conviction_policy, &proposal);
+        if let ProposalStatus::Approved { version } = proposal.status {
+            self.internal_approve_proposal(id, policy, &mut proposal, version);
+        }
+        self.proposals
+            .insert(&id, &VersionedProposal::Default(proposal));
+        self.internal_resolve_dependents(id);
+    }
+
+    /// Returns the tokens staked behind the given proposal to their owners.
+    pub(crate) fn internal_release_conviction(&mut self, proposal: &Proposal) {
+        for (account_id, stake) in proposal.conviction_stakes.iter() {
+            let staked = self.conviction_stakes.get(account_id).unwrap_or_default();
+            self.conviction_stakes
+                .insert(account_id, &(staked - stake.amount.0));
+        }
+    }
+
+    /// Returns the amount of the user's delegated tokens staked behind proposals.
+    pub fn get_conviction_staked(&self, account_id: &AccountId) -> Balance {
+        self.conviction_stakes.get(account_id).unwrap_or_default()
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Stake `amount` of the caller's delegated tokens behind a version of a
+    /// conviction voted proposal, replacing any previous stake on it.
+    /// Conviction already built up by a version stays and decays from there.
+    pub fn stake_conviction(&mut self, id: u64, version: u8, amount: U128) {
+        let mut proposal: Proposal = self.proposals.get(&id).expect("ERR_NO_PROPOSAL").into();
+        let policy = self.policy.get().unwrap().to_policy();
+        let allowed = policy.can_execute_action(
+            self.internal_user_info(),
+            &proposal.kind,
+            &Action::VoteApprove,
+        );
+        assert!(allowed, "ERR_PERMISSION_DENIED");
+        assert_eq!(
+            proposal.status,
+            ProposalStatus::InProgress,
+            "ERR_PROPOSAL_NOT_IN_PROGRESS"
+        );
+        let conviction_policy = policy
+            .get_vote_policy(&proposal.kind)
+            .unwrap_or(&policy.default_vote_policy)
+            .conviction
+            .clone()
+            .expect("ERR_NOT_CONVICTION_VOTING");
+        assert_ne!(
+            policy.proposal_phase(&proposal),
+            ProposalPhase::Discussion,
+            "ERR_DISCUSSION_PHASE"
+        );
+        assert!(version < proposal.versions.len() as u8, "ERR_NO_PROPOSAL_VERSION");
+        assert!(!proposal.remove_flag[version as usize], "ERR_PROPOSAL_REMOVED");
+        assert!(amount.0 > 0, "ERR_ZERO_STAKE");
+
+        let sender_id = env::predecessor_account_id();
+        let mut staked = self.get_conviction_staked(&sender_id);
+        if let Some(old_stake) = proposal.conviction_stakes.get(&sender_id).cloned() {
+            staked -= old_stake.amount.0;
+            proposal.update_conviction(&conviction_policy, old_stake.version, old_stake.amount.0, false);
+        }
+        staked += amount.0;
+        assert!(staked <= self.get_user_weight(&sender_id), "ERR_NOT_ENOUGH_DELEGATED");
+        self.conviction_stakes.insert(&sender_id, &staked);
+        proposal.update_conviction(&conviction_policy, version, amount.0, true);
+        proposal.conviction_stakes.insert(
+            sender_id,
+            ConvictionStake {
+                version,
+                amount,
+                since: WrappedTimestamp::from(env::block_timestamp()),
+            },
+        );
+        self.internal_check_conviction(id, &policy, &conviction_policy, proposal);
+    }
+
+    /// Approve a conviction voted proposal once the conviction of one of its versions
+    /// crossed the threshold. Anyone can call this.
+    pub fn check_conviction(&mut self, id: u64) {
+        let proposal: Proposal = self.proposals.get(&id).expect("ERR_NO_PROPOSAL").into();
+        let policy = self.policy.get().unwrap().to_policy();
+        assert_eq!(
+            proposal.status,
+            ProposalStatus::InProgress,
+            "ERR_PROPOSAL_NOT_IN_PROGRESS"
+        );
+        let conviction_policy = policy
+            .get_vote_policy(&proposal.kind)
+            .unwrap_or(&policy.default_vote_policy)
+            .conviction
+            .clone()
+            .expect("ERR_NOT_CONVICTION_VOTING");
+        self.internal_check_conviction(id, &policy, &conviction_policy, proposal);
+    }
+
+    /// Withdraw the caller's stake from a conviction voted proposal.
+    pub fn withdraw_conviction(&mut self, id: u64) {
+        let mut proposal: Proposal = self.proposals.get(&id).expect("ERR_NO_PROPOSAL").into();
+        let sender_id = env::predecessor_account_id();
+        let stake = proposal
+            .conviction_stakes
+            .remove(&sender_id)
+            .expect("ERR_NO_STAKE");
+        // Stakes of finished proposals are already released, those of proposals
+        // being moved are only released once the hub accepted them.
+        let moving = proposal.status == ProposalStatus::Moved && proposal.moved_to.is_none();
+        if moving
+            || matches!(
+                proposal.status,
+                ProposalStatus::InProgress | ProposalStatus::Queued { .. }
+            )
+        {
+            let staked = self.get_conviction_staked(&sender_id);
+            self.conviction_stakes
+                .insert(&sender_id, &(staked - stake.amount.0));
+        }
+        if moving || proposal.status == ProposalStatus::InProgress {
+            let policy = self.policy.get().unwrap().to_policy();
+            if let Some(conviction_policy) = &policy
+                .get_vote_policy(&proposal.kind)
+                .unwrap_or(&policy.default_vote_policy)
+                .conviction
+            {
+                proposal.update_conviction(conviction_policy, stake.version, stake.amount.0, false);
+            }
+        }
+        self.proposals
+            .insert(&id, &VersionedProposal::Default(proposal));
+    }
+
+    /// Returns the current conviction and threshold of each version of the given proposal.
+    pub fn get_proposal_conviction(&self, id: u64) -> Vec<ConvictionOutput> {
+        let proposal: Proposal = self.proposals.get(&id).expect("ERR_NO_PROPOSAL").into();
+        let policy = self.policy.get().unwrap().to_policy();
+        let conviction_policy = policy
+            .get_vote_policy(&proposal.kind)
+            .unwrap_or(&policy.default_vote_policy)
+            .conviction
+            .clone()
+            .expect("ERR_NOT_CONVICTION_VOTING");
+        self.internal_conviction(&conviction_policy, &proposal)
+            .into_iter()
+            .map(|(conviction, threshold)| ConvictionOutput {
+                conviction: U128(conviction),
+                threshold: threshold.map(U128),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::json_types::WrappedDuration;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    use crate::policy::WeightOrRatio;
+    use crate::test_utils::*;
+
+    use super::*;
+
+    #[test]
+    fn test_conviction_vote() {
+        let mut context = VMContextBuilder::new();
+        let mut policy = VersionedPolicy::Default(vec![accounts(1).into()]).upgrade();
+        policy.to_policy_mut().default_vote_policy.conviction = Some(ConvictionPolicy {
+            half_life: WrappedDuration::from(100),
+            min_threshold: WeightOrRatio::Weight(U128(50)),
+            spending_limit: WeightOrRatio::Ratio(1, 2),
+        });
+        let mut contract = new_contract(&mut context, policy);
+        contract.delegations.insert(&accounts(1).into(), &100);
+        contract.total_delegation_amount = 100;
+        let id = create_proposal(&mut context, &mut contract);
+
+        contract.stake_conviction(id, 0, U128(100));
+        assert_eq!(contract.get_conviction_staked(&accounts(1).into()), 100);
+        // requesting a fifth of the spending limit raises the threshold to 77.
+        testing_env!(context.block_timestamp(50).build());
+        let conviction = contract.get_proposal_conviction(id);
+        assert_eq!(conviction[0].conviction, U128(30));
+        assert_eq!(conviction[0].threshold, Some(U128(77)));
+
+        // conviction decays once the stake is withdrawn.
+        contract.withdraw_conviction(id);
+        testing_env!(context.block_timestamp(150).build());
+        assert_eq!(contract.get_proposal_conviction(id)[0].conviction, U128(15));
+        contract.stake_conviction(id, 0, U128(100));
+
+        // conviction keeps building up well past the proposal period, and anyone
+        // can pass the proposal once it crossed the threshold.
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .block_timestamp(1_000_000_000 * 24 * 60 * 60 * 8)
+            .build());
+        contract.check_conviction(id);
+        assert_eq!(
+            contract.get_proposal(id).proposal.status,
+            ProposalStatus::Approved{ version: 0 }
+        );
+        assert_eq!(contract.get_conviction_staked(&accounts(1).into()), 0);
+    }
+}
@@ -1,13 +1,182 @@
 use crate::*;
+use crate::policy::integer_sqrt;
+
+/// Delegated amount from the given time onwards.
+#[derive(BorshSerialize, BorshDeserialize, Clone)]
+pub struct Checkpoint {
+    pub timestamp: u64,
+    pub amount: Balance,
+}
+
+/// Returns the amount of the last checkpoint strictly before `timestamp`, so that
+/// changes made in the same block as a snapshot are left out of it.
+fn checkpoint_at(len: u64, get: impl Fn(u64) -> Checkpoint, timestamp: u64) -> Option<Balance> {
+    // Find the first checkpoint at or after the timestamp.
+    let (mut low, mut high) = (0, len);
+    while low < high {
+        let mid = (low + high) / 2;
+        if get(mid).timestamp < timestamp {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    if low == 0 {
+        None
+    } else {
+        Some(get(low - 1).amount)
+    }
+}
+
+/// Records the checkpoint, replacing the last one if it was taken at the same time.
+fn push_checkpoint(checkpoints: &mut Vector<Checkpoint>, checkpoint: Checkpoint) {
+    let last_index = checkpoints.len() - 1;
+    if checkpoints.get(last_index).unwrap().timestamp == checkpoint.timestamp {
+        checkpoints.replace(last_index, &checkpoint);
+    } else {
+        checkpoints.push(&checkpoint);
+    }
+}
 
 impl Contract {
     pub fn get_user_weight(&self, account_id: &AccountId) -> Balance {
         self.delegations.get(account_id).unwrap_or_default()
     }
+
+    /// Returns the delegated amount of the user just before the given time.
+    /// Users without checkpoints haven't changed their delegation since checkpoints were introduced.
+    pub fn get_user_weight_at(&self, account_id: &AccountId, timestamp: u64) -> Balance {
+        match self.delegation_checkpoints.get(account_id) {
+            Some(checkpoints) => checkpoint_at(
+                checkpoints.len(),
+                |i| checkpoints.get(i).unwrap(),
+                timestamp,
+            )
+            .unwrap_or_default(),
+            None => self.get_user_weight(account_id),
+        }
+    }
+
+    /// Returns the total delegated amount just before the given time.
+    pub fn get_total_delegation_at(&self, timestamp: u64) -> Balance {
+        if self.total_delegation_checkpoints.is_empty() {
+            return self.total_delegation_amount;
+        }
+        checkpoint_at(
+            self.total_delegation_checkpoints.len(),
+            |i| self.total_delegation_checkpoints.get(i).unwrap(),
+            timestamp,
+        )
+        .unwrap_or_default()
+    }
+
+    /// Returns the sum of the square roots of the delegations just before the given time.
+    pub fn get_total_quadratic_weight_at(&self, timestamp: u64) -> Balance {
+        if self.quadratic_weight_checkpoints.is_empty() {
+            return self.total_quadratic_weight;
+        }
+        checkpoint_at(
+            self.quadratic_weight_checkpoints.len(),
+            |i| self.quadratic_weight_checkpoints.get(i).unwrap(),
+            timestamp,
+        )
+        .unwrap_or_default()
+    }
+
+    /// Records that the given amount delegated before the migration is now counted in
+    /// the quadratic total.
+    fn internal_count_unmigrated(&mut self, amount: Balance) {
+        if self.unmigrated_delegation_amount == 0 {
+            return;
+        }
+        self.unmigrated_delegation_amount -= std::cmp::min(amount, self.unmigrated_delegation_amount);
+        if self.unmigrated_delegation_amount == 0 {
+            self.delegations_migrated_at = Some(env::block_timestamp());
+        }
+    }
+
+    /// Sets the delegation of the user and the total delegation, recording checkpoints of both.
+    fn internal_set_delegation(&mut self, account_id: &AccountId, amount: Balance, total: Balance) {
+        let now = env::block_timestamp();
+        let prev_amount = self.delegations.get(account_id).unwrap_or_default();
+        // Delegations made before the history was recorded are not in the quadratic total yet.
+        let counted_amount = if self.delegation_checkpoints.contains_key(account_id) {
+            prev_amount
+        } else {
+            self.internal_count_unmigrated(prev_amount);
+            0
+        };
+        let mut checkpoints = self.delegation_checkpoints.get(account_id).unwrap_or_else(|| {
+            let mut checkpoints = Vector::new(StorageKeys::AccountCheckpoints {
+                account_id: account_id.clone(),
+            });
+            // Keep the amount delegated before the history was recorded.
+            checkpoints.push(&Checkpoint { timestamp: 0, amount: prev_amount });
+            checkpoints
+        });
+        push_checkpoint(&mut checkpoints, Checkpoint { timestamp: now, amount });
+        self.delegation_checkpoints.insert(account_id, &checkpoints);
+        self.delegations.insert(account_id, &amount);
+
+        if self.total_delegation_checkpoints.is_empty() {
+            self.total_delegation_checkpoints.push(&Checkpoint {
+                timestamp: 0,
+                amount: self.total_delegation_amount,
+            });
+        }
+        push_checkpoint(
+            &mut self.total_delegation_checkpoints,
+            Checkpoint { timestamp: now, amount: total },
+        );
+        self.total_delegation_amount = total;
+
+        self.internal_set_quadratic_weight(
+            self.total_quadratic_weight + integer_sqrt(amount) - integer_sqrt(counted_amount),
+        );
+    }
+
+    /// Sets the sum of the square roots of the delegations, recording a checkpoint of it.
+    fn internal_set_quadratic_weight(&mut self, total: Balance) {
+        if self.quadratic_weight_checkpoints.is_empty() {
+            self.quadratic_weight_checkpoints.push(&Checkpoint {
+                timestamp: 0,
+                amount: self.total_quadratic_weight,
+            });
+        }
+        push_checkpoint(
+            &mut self.quadratic_weight_checkpoints,
+            Checkpoint { timestamp: env::block_timestamp(), amount: total },
+        );
+        self.total_quadratic_weight = total;
+    }
 }
 
 #[near_bindgen]
 impl Contract {
+    /// Records the history of the given accounts' delegations made before the migration
+    /// and counts them in the quadratic total. Anyone can call this.
+    pub fn migrate_delegations(&mut self, account_ids: Vec<ValidAccountId>) {
+        let mut total = self.total_quadratic_weight;
+        for account_id in account_ids {
+            let account_id: AccountId = account_id.into();
+            if self.delegation_checkpoints.contains_key(&account_id) {
+                continue;
+            }
+            let amount = match self.delegations.get(&account_id) {
+                Some(amount) => amount,
+                None => continue,
+            };
+            let mut checkpoints = Vector::new(StorageKeys::AccountCheckpoints {
+                account_id: account_id.clone(),
+            });
+            checkpoints.push(&Checkpoint { timestamp: 0, amount });
+            self.delegation_checkpoints.insert(&account_id, &checkpoints);
+            total += integer_sqrt(amount);
+            self.internal_count_unmigrated(amount);
+        }
+        self.internal_set_quadratic_weight(total);
+    }
+
     #[payable]
     pub fn register_delegation(&mut self, account_id: &AccountId) {
         let staking_id = self.staking_id.clone().expect("ERR_NO_STAKING");
@@ -31,9 +200,11 @@ impl Contract {
             .delegations
             .get(account_id)
             .expect("ERR_NOT_REGISTERED");
-        self.delegations
-            .insert(account_id, &(prev_amount + amount.0));
-        self.total_delegation_amount += amount.0;
+        self.internal_set_delegation(
+            account_id,
+            prev_amount + amount.0,
+            self.total_delegation_amount + amount.0,
+        );
     }
 
     pub fn undelegate(&mut self, account_id: &AccountId, amount: U128) {
@@ -45,8 +216,77 @@ impl Contract {
         );
         let prev_amount = self.delegations.get(account_id).unwrap_or_default();
         assert!(prev_amount >= amount.0, "ERR_INVALID_STAKING_CONTRACT");
-        self.delegations
-            .insert(account_id, &(prev_amount - amount.0));
-        self.total_delegation_amount -= amount.0;
+        assert!(
+            prev_amount - amount.0 >= self.get_conviction_staked(account_id),
+            "ERR_STAKED_IN_CONVICTION"
+        );
+        assert!(
+            prev_amount - amount.0 >= self.get_locked_delegation(account_id),
+            "ERR_LOCKED"
+        );
+        self.internal_set_delegation(
+            account_id,
+            prev_amount - amount.0,
+            self.total_delegation_amount - amount.0,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    use crate::policy::WeightKind;
+    use crate::test_utils::*;
+
+    use super::*;
+
+    #[test]
+    fn test_snapshot_voting_power() {
+        let mut context = VMContextBuilder::new();
+        let mut policy = VersionedPolicy::Default(vec![
+            accounts(1).into(),
+            accounts(2).into(),
+            accounts(3).into(),
+        ])
+        .upgrade();
+        policy.to_policy_mut().default_vote_policy.weight_kind = WeightKind::TokenWeight;
+        let mut contract = new_contract(&mut context, policy);
+        contract.staking_id = Some(accounts(0).into());
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.delegations.insert(&accounts(1).into(), &0);
+        contract.delegations.insert(&accounts(2).into(), &0);
+        contract.delegations.insert(&accounts(3).into(), &0);
+        contract.delegate(&accounts(1).into(), U128(40));
+        contract.delegate(&accounts(3).into(), U128(60));
+
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .block_timestamp(10)
+            .build());
+        let id = create_proposal(&mut context, &mut contract);
+        contract.reject(id);
+
+        // delegations made in the same block as the submission are left out.
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.delegate(&accounts(2).into(), U128(50));
+
+        // the tokens move on to another account after the proposal was submitted.
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(20)
+            .build());
+        contract.undelegate(&accounts(1).into(), U128(40));
+        contract.delegate(&accounts(2).into(), U128(40));
+        assert_eq!(contract.get_user_weight_at(&accounts(2).into(), 10), 0);
+        assert_eq!(contract.get_total_delegation_at(10), 100);
+        assert_eq!(contract.delegation_balance_at(accounts(1), 10.into()).0, 40);
+
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.approve(id, 0);
+        let proposal = contract.get_proposal(id).proposal;
+        assert_eq!(proposal.approve_count[0], 0);
+        assert_eq!(proposal.reject_count, 40);
     }
 }
@@ -1,5 +1,5 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::{LazyOption, LookupMap};
+use near_sdk::collections::{LazyOption, LookupMap, TreeMap, UnorderedSet, Vector};
 #[cfg(target_arch = "wasm32")]
 use near_sdk::env::BLOCKCHAIN_INTERFACE;
 use near_sdk::json_types::{Base58CryptoHash, ValidAccountId, U128};
@@ -9,18 +9,42 @@ use near_sdk::{
 };
 
 use crate::bounties::{Bounty, BountyClaim, VersionedBounty};
-pub use crate::policy::{Policy, RoleKind, RolePermission, VersionedPolicy, VotePolicy};
+use crate::delegation::Checkpoint;
+pub use crate::payments::{PaymentStream, RecurringPayment};
+pub use crate::vesting::{Vesting, VestingSchedule};
+pub use crate::policy::{
+    BallotKind, ConvictionPolicy, Policy, QuorumKind, RoleKind, RolePermission, SecretBallot,
+    UnrevealedVotes, VersionedPolicy, VotePolicy,
+};
 use crate::proposals::VersionedProposal;
-pub use crate::proposals::{Proposal, Instruction, ProposalKind, ProposalStatus};
+pub use crate::proposals::{
+    ExecutedInstruction, Instruction, InstructionOutcome, Prerequisite, Proposal, ProposalKind,
+    ProposalLink, ProposalOptions, ProposalPhase, ProposalStatus, ProposalVersion, VoteChoice,
+    VotedAmount,
+};
+pub use crate::templates::ProposalTemplate;
 pub use crate::types::{Action, Config};
+pub use crate::vote_delegation::VoteDelegation;
+use crate::vote_escrow::VoteEscrowCheckpoint;
+pub use crate::vote_escrow::VoteLock;
 
 mod bounties;
+mod commit_reveal;
+mod conviction;
 mod delegation;
+mod payments;
 mod policy;
 mod proposals;
+mod templates;
 mod types;
+mod vesting;
+mod vote_delegation;
+mod vote_escrow;
 pub mod views;
 
+#[cfg(test)]
+mod test_utils;
+
 near_sdk::setup_alloc!();
 
 #[cfg(target_arch = "wasm32")]
@@ -36,6 +60,22 @@ pub enum StorageKeys {
     BountyClaimers,
     BountyClaimCounts,
     Blobs,
+    ConvictionStakes,
+    DelegationCheckpoints,
+    TotalDelegationCheckpoints,
+    VoteDelegations,
+    VoteDelegators,
+    VoteLocks,
+    Payments,
+    Vestings,
+    ReservedAmounts,
+    QueuedProposals,
+    DependentProposals,
+    AccountCheckpoints { account_id: AccountId },
+    AccountVoteLocks { account_id: AccountId },
+    VoteEscrowCheckpoints,
+    LockExpiries,
+    QuadraticWeightCheckpoints,
 }
 
 #[near_bindgen]
@@ -48,6 +88,8 @@ pub struct Contract {
 
     /// Amount of $NEAR locked for storage / bonds.
     pub locked_amount: Balance,
+    /// Amount of $NEAR kept from the bonds of removed proposals.
+    pub slashed_amount: Balance,
 
     /// Vote staking contract id. That contract must have this account as owner.
     pub staking_id: Option<AccountId>,
@@ -55,11 +97,40 @@ pub struct Contract {
     pub total_delegation_amount: Balance,
     /// Delegations per user.
     pub delegations: LookupMap<AccountId, Balance>,
+    /// History of delegations per user, used to snapshot voting power.
+    pub delegation_checkpoints: LookupMap<AccountId, Vector<Checkpoint>>,
+    /// History of the delegated token total amount.
+    pub total_delegation_checkpoints: Vector<Checkpoint>,
+    /// Sum of the square roots of the delegations, the total weight of quadratic voting.
+    pub total_quadratic_weight: Balance,
+    /// History of the sum of the square roots of the delegations.
+    pub quadratic_weight_checkpoints: Vector<Checkpoint>,
+    /// Amount delegated before the migration that isn't counted in the quadratic total yet.
+    pub unmigrated_delegation_amount: Balance,
+    /// Time the delegations made before the migration were all counted in the quadratic
+    /// total, if there were any. Quadratic thresholds can only be taken after it.
+    pub delegations_migrated_at: Option<u64>,
+    /// Delegated tokens per user that are staked behind conviction voted proposals.
+    pub conviction_stakes: LookupMap<AccountId, Balance>,
+    /// Vote delegation of each member to another member.
+    pub vote_delegations: LookupMap<AccountId, VoteDelegation>,
+    /// Members that delegated their vote per delegate.
+    pub vote_delegators: LookupMap<AccountId, Vec<AccountId>>,
+    /// History of the vote escrow lock per user.
+    pub vote_locks: LookupMap<AccountId, Vector<VoteLock>>,
+    /// History of the total voting power of the vote escrow locks.
+    pub vote_escrow_checkpoints: Vector<VoteEscrowCheckpoint>,
+    /// Amount of the vote escrow locks ending at each time.
+    pub lock_expiries: TreeMap<u64, Balance>,
 
     /// Last available id for the proposals.
     pub last_proposal_id: u64,
     /// Proposal map from ID to proposal information.
     pub proposals: LookupMap<u64, VersionedProposal>,
+    /// Ids of the proposals queued for execution.
+    pub queued_proposals: UnorderedSet<u64>,
+    /// Ids of the queued proposals that have the given proposal as a prerequisite.
+    pub dependent_proposals: LookupMap<u64, Vec<u64>>,
 
     /// Last available id for the bounty.
     pub last_bounty_id: u64,
@@ -70,36 +141,91 @@ pub struct Contract {
     /// Count of claims per bounty.
     pub bounty_claims_count: LookupMap<u64, u32>,
 
+    /// Last available id for the recurring payments.
+    pub last_payment_id: u64,
+    /// Recurring payments map from ID to payment information.
+    pub payments: LookupMap<u64, PaymentStream>,
+
+    /// Last available id for the vesting schedules.
+    pub last_vesting_id: u64,
+    /// Vesting schedules map from ID to vesting information.
+    pub vestings: LookupMap<u64, Vesting>,
+    /// Amount of each token reserved for vesting schedules and recurring payments and not claimed yet.
+    pub reserved_amounts: LookupMap<AccountId, Balance>,
+
     /// Large blob storage.
     pub blobs: LookupMap<CryptoHash, AccountId>,
 }
 
+/// Contract state as stored before ballot kinds were introduced.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct ContractV1 {
+    pub config: LazyOption<Config>,
+    pub policy: LazyOption<VersionedPolicy>,
+    pub locked_amount: Balance,
+    pub staking_id: Option<AccountId>,
+    pub total_delegation_amount: Balance,
+    pub delegations: LookupMap<AccountId, Balance>,
+    pub last_proposal_id: u64,
+    pub proposals: LookupMap<u64, VersionedProposal>,
+    pub last_bounty_id: u64,
+    pub bounties: LookupMap<u64, VersionedBounty>,
+    pub bounty_claimers: LookupMap<AccountId, Vec<BountyClaim>>,
+    pub bounty_claims_count: LookupMap<u64, u32>,
+    pub blobs: LookupMap<CryptoHash, AccountId>,
+}
+
 #[near_bindgen]
 impl Contract {
     #[init]
     pub fn new(config: Config, policy: VersionedPolicy) -> Self {
+        policy.clone().upgrade().to_policy().assert_valid_templates();
         Self {
             config: LazyOption::new(StorageKeys::Config, Some(&config)),
             policy: LazyOption::new(StorageKeys::Policy, Some(&policy.upgrade())),
             staking_id: None,
             total_delegation_amount: 0,
             delegations: LookupMap::new(StorageKeys::Delegations),
+            delegation_checkpoints: LookupMap::new(StorageKeys::DelegationCheckpoints),
+            total_delegation_checkpoints: Vector::new(StorageKeys::TotalDelegationCheckpoints),
+            total_quadratic_weight: 0,
+            quadratic_weight_checkpoints: Vector::new(StorageKeys::QuadraticWeightCheckpoints),
+            unmigrated_delegation_amount: 0,
+            delegations_migrated_at: None,
+            conviction_stakes: LookupMap::new(StorageKeys::ConvictionStakes),
+            vote_delegations: LookupMap::new(StorageKeys::VoteDelegations),
+            vote_delegators: LookupMap::new(StorageKeys::VoteDelegators),
+            vote_locks: LookupMap::new(StorageKeys::VoteLocks),
+            vote_escrow_checkpoints: Vector::new(StorageKeys::VoteEscrowCheckpoints),
+            lock_expiries: TreeMap::new(StorageKeys::LockExpiries),
             last_proposal_id: 0,
             proposals: LookupMap::new(StorageKeys::Proposals),
+            queued_proposals: UnorderedSet::new(StorageKeys::QueuedProposals),
+            dependent_proposals: LookupMap::new(StorageKeys::DependentProposals),
             last_bounty_id: 0,
             bounties: LookupMap::new(StorageKeys::Bounties),
             bounty_claimers: LookupMap::new(StorageKeys::BountyClaimers),
             bounty_claims_count: LookupMap::new(StorageKeys::BountyClaimCounts),
+            last_payment_id: 0,
+            payments: LookupMap::new(StorageKeys::Payments),
+            last_vesting_id: 0,
+            vestings: LookupMap::new(StorageKeys::Vestings),
+            reserved_amounts: LookupMap::new(StorageKeys::ReservedAmounts),
             blobs: LookupMap::new(StorageKeys::Blobs),
             // TODO: only accounts for contract but not for this state object. Can just add fixed size of it.
             locked_amount: env::storage_byte_cost() * (env::storage_usage() as u128),
+            slashed_amount: 0,
         }
     }
 
     /// Should only be called by this contract on migration.
-    /// This is NOOP implementation. KEEP IT if you haven't changed contract state.
-    /// If you have changed state, you need to implement migration from old state (keep the old struct with different name to deserialize it first).
-    /// After migrate goes live on MainNet, return this implementation for next updates.
+    /// Migrates the state stored before ballot kinds were introduced (`ContractV1`):
+    /// upgrades the policy and every proposal, and sets up the new collections.
+    /// Bonds of proposals still in progress are locked, as they are now returned from the locked amount.
+    /// Delegations can't be listed here, so their history and quadratic total are built
+    /// afterwards with `migrate_delegations`, or once each delegation changes. Quadratic
+    /// thresholds are refused until every delegation made before the migration is counted.
+    /// After migrate goes live on MainNet, return the NOOP implementation for next updates.
     #[init(ignore_state)]
     pub fn migrate() -> Self {
         assert_eq!(
@@ -107,8 +233,59 @@ impl Contract {
             env::current_account_id(),
             "ERR_NOT_ALLOWED"
         );
-        let this: Contract = env::state_read().expect("ERR_CONTRACT_IS_NOT_INITIALIZED");
-        this
+        let old: ContractV1 = env::state_read().expect("ERR_CONTRACT_IS_NOT_INITIALIZED");
+        let mut policy = old.policy;
+        let current = policy.get().unwrap().upgrade();
+        policy.set(&current);
+        let bond = current.to_policy().proposal_bond;
+
+        let mut proposals = old.proposals;
+        let mut locked_amount = old.locked_amount;
+        for id in 0..old.last_proposal_id {
+            if let Some(proposal) = proposals.get(&id) {
+                let proposal: Proposal = proposal.upgrade(bond).into();
+                if proposal.status == ProposalStatus::InProgress {
+                    locked_amount += bond.0 * proposal.versions.len() as Balance;
+                }
+                proposals.insert(&id, &VersionedProposal::Default(proposal));
+            }
+        }
+
+        Self {
+            config: old.config,
+            policy,
+            staking_id: old.staking_id,
+            total_delegation_amount: old.total_delegation_amount,
+            delegations: old.delegations,
+            delegation_checkpoints: LookupMap::new(StorageKeys::DelegationCheckpoints),
+            total_delegation_checkpoints: Vector::new(StorageKeys::TotalDelegationCheckpoints),
+            total_quadratic_weight: 0,
+            quadratic_weight_checkpoints: Vector::new(StorageKeys::QuadraticWeightCheckpoints),
+            unmigrated_delegation_amount: old.total_delegation_amount,
+            delegations_migrated_at: None,
+            conviction_stakes: LookupMap::new(StorageKeys::ConvictionStakes),
+            vote_delegations: LookupMap::new(StorageKeys::VoteDelegations),
+            vote_delegators: LookupMap::new(StorageKeys::VoteDelegators),
+            vote_locks: LookupMap::new(StorageKeys::VoteLocks),
+            vote_escrow_checkpoints: Vector::new(StorageKeys::VoteEscrowCheckpoints),
+            lock_expiries: TreeMap::new(StorageKeys::LockExpiries),
+            last_proposal_id: old.last_proposal_id,
+            proposals,
+            queued_proposals: UnorderedSet::new(StorageKeys::QueuedProposals),
+            dependent_proposals: LookupMap::new(StorageKeys::DependentProposals),
+            last_bounty_id: old.last_bounty_id,
+            bounties: old.bounties,
+            bounty_claimers: old.bounty_claimers,
+            bounty_claims_count: old.bounty_claims_count,
+            last_payment_id: 0,
+            payments: LookupMap::new(StorageKeys::Payments),
+            last_vesting_id: 0,
+            vestings: LookupMap::new(StorageKeys::Vestings),
+            reserved_amounts: LookupMap::new(StorageKeys::ReservedAmounts),
+            blobs: old.blobs,
+            locked_amount,
+            slashed_amount: 0,
+        }
     }
 
     /// Remove blob from contract storage and pay back to original storer.
@@ -200,33 +377,26 @@ pub extern "C" fn store_blob() {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+
     use near_sdk::test_utils::{accounts, VMContextBuilder};
     use near_sdk::{testing_env, MockedBlockchain};
     use near_sdk_sim::to_yocto;
 
-    use crate::proposals::ProposalStatus;
+    use near_sdk::json_types::{WrappedDuration, WrappedTimestamp};
+
+    use crate::policy::{PolicyV1, VotePolicyV1, WeightKind, WeightOrRatio};
+    use crate::proposals::{ProposalStatus, ProposalStatusV1, ProposalV1, ProposalVersionV1, VoteV1};
+    use crate::test_utils::*;
     use crate::types::BASE_TOKEN;
 
     use super::*;
 
-    fn create_proposal(context: &mut VMContextBuilder, contract: &mut Contract) -> u64 {
-        testing_env!(context.attached_deposit(to_yocto("1")).build());
-        contract.propose(
-            "test".to_string(),
-            vec![Instruction::Transfer {
-                token_id: BASE_TOKEN.to_string(),
-                receiver_id: accounts(2).into(),
-                amount: U128(to_yocto("100")),
-            }],
-        )
-    }
-
     #[test]
     fn test_basics() {
         let mut context = VMContextBuilder::new();
-        testing_env!(context.predecessor_account_id(accounts(1)).build());
-        let mut contract = Contract::new(
-            Config::test_config(),
+        let mut contract = new_contract(
+            &mut context,
             VersionedPolicy::Default(vec![accounts(1).into()]),
         );
         let id = create_proposal(&mut context, &mut contract);
@@ -269,9 +439,8 @@ mod tests {
     #[should_panic(expected = "ERR_PERMISSION_DENIED")]
     fn test_remove_proposal_denied() {
         let mut context = VMContextBuilder::new();
-        testing_env!(context.predecessor_account_id(accounts(1)).build());
-        let mut contract = Contract::new(
-            Config::test_config(),
+        let mut contract = new_contract(
+            &mut context,
             VersionedPolicy::Default(vec![accounts(1).into()]),
         );
         let id = create_proposal(&mut context, &mut contract);
@@ -282,12 +451,11 @@ mod tests {
     #[test]
     fn test_remove_proposal_allowed() {
         let mut context = VMContextBuilder::new();
-        testing_env!(context.predecessor_account_id(accounts(1)).build());
         let mut policy = VersionedPolicy::Default(vec![accounts(1).into()]).upgrade();
         policy.to_policy_mut().roles[1]
             .permissions
             .insert("*:RemoveProposal".to_string());
-        let mut contract = Contract::new(Config::test_config(), policy);
+        let mut contract = new_contract(&mut context, policy);
         let id = create_proposal(&mut context, &mut contract);
         assert_eq!(contract.get_proposal(id).proposal.versions[0].description, "test");
         contract.remove(id);
@@ -297,9 +465,8 @@ mod tests {
     #[test]
     fn test_vote_expired_proposal() {
         let mut context = VMContextBuilder::new();
-        testing_env!(context.predecessor_account_id(accounts(1)).build());
-        let mut contract = Contract::new(
-            Config::test_config(),
+        let mut contract = new_contract(
+            &mut context,
             VersionedPolicy::Default(vec![accounts(1).into()]),
         );
         let id = create_proposal(&mut context, &mut contract);
@@ -310,15 +477,419 @@ mod tests {
     }
 
     #[test]
-    fn test_vote_twice() {
+    fn test_quadratic_vote() {
         let mut context = VMContextBuilder::new();
+        let mut policy = VersionedPolicy::Default(vec![
+            accounts(1).into(),
+            accounts(2).into(),
+            accounts(3).into(),
+        ])
+        .upgrade();
+        policy.to_policy_mut().default_vote_policy.weight_kind = WeightKind::Quadratic;
+        let mut contract = new_contract(&mut context, policy);
+        contract.delegations.insert(&accounts(1).into(), &10_000);
+        contract.delegations.insert(&accounts(2).into(), &100);
+        contract.delegations.insert(&accounts(3).into(), &100);
+        contract.total_delegation_amount = 10_200;
+        contract.total_quadratic_weight = 120;
+        let id = create_proposal(&mut context, &mut contract);
+        assert_eq!(contract.get_total_quadratic_weight_at(env::block_timestamp()), 120);
+
+        // The sum of the square roots is 120, so 61 is needed. Small holders carry 10 each.
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.approve(id, 0);
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.approve(id, 0);
+        let proposal = contract.get_proposal(id).proposal;
+        assert_eq!(proposal.approve_count[0], 20);
+        assert_eq!(proposal.status, ProposalStatus::InProgress);
+
         testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.approve(id, 0);
+        assert_eq!(
+            contract.get_proposal(id).proposal.status,
+            ProposalStatus::Approved{ version: 0 }
+        );
+    }
+
+    #[test]
+    fn test_ranked_vote() {
+        let mut context = VMContextBuilder::new();
+        let mut policy = VersionedPolicy::Default(vec![
+            accounts(1).into(),
+            accounts(2).into(),
+            accounts(3).into(),
+        ])
+        .upgrade();
+        policy.to_policy_mut().default_vote_policy.ballot_kind = BallotKind::Ranked;
+        let mut contract = new_contract(&mut context, policy);
+        let id = create_proposal(&mut context, &mut contract);
+        for _ in 0..2 {
+            contract.counter_propose(
+                id,
+                "counter".to_string(),
+                vec![Instruction::Transfer {
+                    token_id: BASE_TOKEN.to_string(),
+                    receiver_id: accounts(3).into(),
+                    amount: U128(to_yocto("50")),
+                }],
+            );
+        }
+
+        contract.vote_ranked(id, vec![0, 2], HashMap::new());
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.vote_ranked(id, vec![1, 2], HashMap::new());
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.vote_ranked(id, vec![2, 1], HashMap::new());
+        // a single vote for version 1 would now reach the threshold of 2,
+        // but ranked ballots are only tallied once the period is over.
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.approve(id, 1);
+        contract.vote_ranked(id, vec![0, 2], HashMap::new());
+        assert_eq!(contract.get_proposal(id).proposal.approve_count, vec![1, 1, 1]);
+        assert_eq!(
+            contract.get_proposal(id).proposal.status,
+            ProposalStatus::InProgress
+        );
+
+        // all versions tie, the most recent one is eliminated and its ballot
+        // moves on to version 1.
+        testing_env!(context
+            .block_timestamp(1_000_000_000 * 24 * 60 * 60 * 8)
+            .build());
+        contract.finalize(id);
+        assert_eq!(
+            contract.get_proposal(id).proposal.status,
+            ProposalStatus::Approved{ version: 1 }
+        );
+    }
+
+    #[test]
+    fn test_approval_vote() {
+        let mut context = VMContextBuilder::new();
+        let mut policy = VersionedPolicy::Default(vec![
+            accounts(1).into(),
+            accounts(2).into(),
+            accounts(3).into(),
+        ])
+        .upgrade();
+        policy.to_policy_mut().default_vote_policy.ballot_kind = BallotKind::Approval;
+        let mut contract = new_contract(&mut context, policy);
+        let id = create_proposal(&mut context, &mut contract);
+        for _ in 0..2 {
+            contract.counter_propose(
+                id,
+                "counter".to_string(),
+                vec![Instruction::Transfer {
+                    token_id: BASE_TOKEN.to_string(),
+                    receiver_id: accounts(3).into(),
+                    amount: U128(to_yocto("50")),
+                }],
+            );
+        }
+
+        contract.vote_approval(id, vec![0, 1], HashMap::new());
+        // revoting reverses every previously approved version.
+        contract.vote_approval(id, vec![1, 2], HashMap::new());
+        assert_eq!(contract.get_proposal(id).proposal.approve_count, vec![0, 1, 1]);
+
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.vote_approval(id, vec![0, 2], HashMap::new());
+        assert_eq!(
+            contract.get_proposal(id).proposal.status,
+            ProposalStatus::Approved{ version: 2 }
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_WRONG_BALLOT_KIND")]
+    fn test_approval_vote_wrong_ballot() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = new_contract(
+            &mut context,
+            VersionedPolicy::Default(vec![accounts(1).into()]),
+        );
+        let id = create_proposal(&mut context, &mut contract);
+        contract.vote_approval(id, vec![0], HashMap::new());
+    }
+
+    #[test]
+    fn test_voted_amount() {
+        let mut context = VMContextBuilder::new();
+        let mut policy = VersionedPolicy::Default(vec![
+            accounts(1).into(),
+            accounts(2).into(),
+            accounts(3).into(),
+        ])
+        .upgrade();
+        policy.to_policy_mut().default_vote_policy.weight_kind = WeightKind::TokenWeight;
+        let mut contract = new_contract(&mut context, policy);
+        contract.delegations.insert(&accounts(1).into(), &10);
+        contract.delegations.insert(&accounts(2).into(), &30);
+        contract.total_delegation_amount = 40;
+        testing_env!(context.attached_deposit(to_yocto("1")).build());
+        let id = contract.propose_with_options(
+            "bounty".to_string(),
+            vec![Instruction::AddBounty {
+                bounty: Bounty {
+                    description: "test bounty".to_string(),
+                    token: BASE_TOKEN.to_string(),
+                    amount: U128(100),
+                    times: 1,
+                    max_deadline: WrappedDuration::from(1_000),
+                },
+            }],
+            ProposalOptions {
+                voted_amount: Some(VotedAmount {
+                    instruction: 0,
+                    min: U128(10),
+                    max: U128(200),
+                }),
+                ..ProposalOptions::default()
+            },
+        );
+
+        contract.approve_amount(id, 0, U128(150));
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.approve_amount(id, 0, U128(50));
+        assert_eq!(
+            contract.get_proposal(id).proposal.status,
+            ProposalStatus::Executed{ version: 0 }
+        );
+        // the heavier voter decides the weighted median.
+        assert_eq!(contract.get_bounty(0).bounty.amount, U128(50));
+    }
+
+    #[test]
+    fn test_voted_amount_per_version() {
+        let mut context = VMContextBuilder::new();
+        let mut policy = VersionedPolicy::Default(vec![
+            accounts(1).into(),
+            accounts(2).into(),
+            accounts(3).into(),
+        ])
+        .upgrade();
+        policy.to_policy_mut().default_vote_policy.ballot_kind = BallotKind::Approval;
+        let mut contract = new_contract(&mut context, policy);
+        let id = create_proposal(&mut context, &mut contract);
+        let version = contract.counter_propose_with_options(
+            id,
+            "counter".to_string(),
+            vec![Instruction::Transfer {
+                token_id: BASE_TOKEN.to_string(),
+                receiver_id: accounts(3).into(),
+                amount: U128(50),
+            }],
+            ProposalOptions {
+                voted_amount: Some(VotedAmount {
+                    instruction: 0,
+                    min: U128(10),
+                    max: U128(200),
+                }),
+                ..ProposalOptions::default()
+            },
+        );
+        assert_eq!(version, 1);
+
+        // amounts are only chosen for the approved versions that have a voted amount.
+        contract.vote_approval(id, vec![0, 1], vec![(1, U128(40))].into_iter().collect());
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.vote_approval(id, vec![1], vec![(1, U128(80))].into_iter().collect());
+        let proposal = contract.get_proposal(id).proposal;
+        assert_eq!(proposal.status, ProposalStatus::Approved{ version: 1 });
+        assert_eq!(proposal.decided_amount, Some(U128(40)));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_AMOUNT_OUT_OF_BOUNDS")]
+    fn test_voted_amount_out_of_bounds() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(to_yocto("1"))
+            .build());
         let mut contract = Contract::new(
             Config::test_config(),
+            VersionedPolicy::Default(vec![accounts(1).into()]),
+        );
+        let id = contract.propose_with_options(
+            "test".to_string(),
+            vec![Instruction::Transfer {
+                token_id: BASE_TOKEN.to_string(),
+                receiver_id: accounts(2).into(),
+                amount: U128(100),
+            }],
+            ProposalOptions {
+                voted_amount: Some(VotedAmount {
+                    instruction: 0,
+                    min: U128(10),
+                    max: U128(200),
+                }),
+                ..ProposalOptions::default()
+            },
+        );
+        contract.approve_amount(id, 0, U128(300));
+    }
+
+    #[test]
+    fn test_vote_twice() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = new_contract(
+            &mut context,
             VersionedPolicy::Default(vec![accounts(1).into(), accounts(2).into()]),
         );
         let id = create_proposal(&mut context, &mut contract);
         contract.approve(id, 0);
         contract.approve(id, 0);
     }
+
+    /// Writes the state of the original contract with the given delegations and a
+    /// proposal voted by accounts(1), and migrates it.
+    fn migrate_v1(context: &mut VMContextBuilder, delegations: &[(AccountId, Balance)]) -> Contract {
+        testing_env!(context
+            .current_account_id(accounts(0))
+            .predecessor_account_id(accounts(0))
+            .build());
+        let policy = VersionedPolicy::Default(vec![accounts(1).into(), accounts(2).into()])
+            .upgrade()
+            .to_policy();
+        let mut old = ContractV1 {
+            config: LazyOption::new(StorageKeys::Config, Some(&Config::test_config())),
+            policy: LazyOption::new(
+                StorageKeys::Policy,
+                Some(&VersionedPolicy::V1(PolicyV1 {
+                    proposal_kinds: vec![],
+                    roles: policy.roles,
+                    default_vote_policy: VotePolicyV1 {
+                        weight_kind: WeightKind::RoleWeight,
+                        quorum: U128(0),
+                        threshold: WeightOrRatio::Ratio(1, 2),
+                    },
+                    proposal_bond: U128(to_yocto("1")),
+                    proposal_period: policy.proposal_period,
+                    bounty_bond: policy.bounty_bond,
+                    bounty_forgiveness_period: policy.bounty_forgiveness_period,
+                })),
+            ),
+            locked_amount: 0,
+            staking_id: Some(accounts(5).into()),
+            total_delegation_amount: delegations.iter().map(|(_, amount)| amount).sum(),
+            delegations: LookupMap::new(StorageKeys::Delegations),
+            last_proposal_id: 1,
+            proposals: LookupMap::new(StorageKeys::Proposals),
+            last_bounty_id: 0,
+            bounties: LookupMap::new(StorageKeys::Bounties),
+            bounty_claimers: LookupMap::new(StorageKeys::BountyClaimers),
+            bounty_claims_count: LookupMap::new(StorageKeys::BountyClaimCounts),
+            blobs: LookupMap::new(StorageKeys::Blobs),
+        };
+        for (account_id, amount) in delegations {
+            old.delegations.insert(account_id, amount);
+        }
+        let mut votes = HashMap::new();
+        votes.insert(accounts(1).into(), VoteV1 { choice: 1, weight: 1 });
+        old.proposals.insert(&0, &VersionedProposal::V1(ProposalV1 {
+            kind: "".to_string(),
+            versions: vec![ProposalVersionV1 {
+                proposer: accounts(1).into(),
+                description: "test".to_string(),
+                instructions: vec![Instruction::Vote],
+            }],
+            status: ProposalStatusV1::InProgress,
+            approve_count: vec![1],
+            reject_count: 0,
+            remove_count: vec![0],
+            remove_flag: vec![false],
+            votes,
+            remove_votes: vec![],
+            submission_time: WrappedTimestamp::from(0),
+        }));
+        env::state_write(&old);
+        Contract::migrate()
+    }
+
+    #[test]
+    fn test_migrate() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = migrate_v1(&mut context, &[]);
+        assert_eq!(contract.get_policy().default_vote_policy.ballot_kind, BallotKind::Single);
+        assert_eq!(contract.locked_amount, to_yocto("1"));
+        let proposal = contract.get_proposal(0).proposal;
+        assert_eq!(proposal.versions[0].bond, U128(to_yocto("1")));
+        assert_eq!(proposal.votes[&accounts(1).to_string()].choice, VoteChoice::Approve(0));
+
+        // the migrated vote counts toward the threshold of the second council member
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.approve(0, 0);
+        assert_eq!(
+            contract.get_proposal(0).proposal.status,
+            ProposalStatus::Executed{ version: 0 }
+        );
+        assert_eq!(contract.locked_amount, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_DELEGATIONS_NOT_MIGRATED")]
+    fn test_migrate_quadratic_delegations() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = migrate_v1(
+            &mut context,
+            &[(accounts(1).into(), 10_000), (accounts(2).into(), 100), (accounts(3).into(), 100)],
+        );
+        assert_eq!(contract.unmigrated_delegation_amount, 10_200);
+        let mut policy = contract.policy.get().unwrap();
+        policy.to_policy_mut().default_vote_policy.weight_kind = WeightKind::Quadratic;
+        contract.policy.set(&policy);
+
+        // accounts(1) alone carries 100 of the 120, but only counts once its
+        // delegation changes.
+        testing_env!(context.predecessor_account_id(accounts(5)).block_timestamp(10).build());
+        contract.delegate(&accounts(2).into(), U128(21));
+        contract.undelegate(&accounts(3).into(), U128(19));
+        assert_eq!(contract.total_quadratic_weight, 20);
+        assert_eq!(contract.delegations_migrated_at, None);
+        testing_env!(context.predecessor_account_id(accounts(1)).block_timestamp(20).build());
+        let id = create_proposal(&mut context, &mut contract);
+        contract.approve(id, 0);
+    }
+
+    #[test]
+    fn test_migrate_delegations() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = migrate_v1(
+            &mut context,
+            &[(accounts(1).into(), 10_000), (accounts(2).into(), 100), (accounts(3).into(), 100)],
+        );
+        let mut policy = contract.policy.get().unwrap();
+        policy.to_policy_mut().default_vote_policy.weight_kind = WeightKind::Quadratic;
+        contract.policy.set(&policy);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(4))
+            .account_balance(to_yocto("1000"))
+            .block_timestamp(10)
+            .build());
+        contract.migrate_delegations(vec![accounts(1), accounts(2), accounts(4)]);
+        assert_eq!(contract.unmigrated_delegation_amount, 100);
+        assert_eq!(contract.delegations_migrated_at, None);
+        testing_env!(context.block_timestamp(20).build());
+        contract.migrate_delegations(vec![accounts(3), accounts(2)]);
+        assert_eq!(contract.total_quadratic_weight, 120);
+        assert_eq!(contract.delegations_migrated_at, Some(20));
+        // the delegations made before the migration are in the snapshots.
+        assert_eq!(contract.get_user_weight_at(&accounts(1).into(), 1), 10_000);
+        assert_eq!(contract.get_total_quadratic_weight_at(21), 120);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).block_timestamp(30).build());
+        let id = create_proposal(&mut context, &mut contract);
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.approve(id, 0);
+        assert_eq!(contract.get_proposal(id).proposal.status, ProposalStatus::InProgress);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.approve(id, 0);
+        assert_eq!(
+            contract.get_proposal(id).proposal.status,
+            ProposalStatus::Approved{ version: 0 }
+        );
+    }
 }
@@ -0,0 +1,385 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{WrappedDuration, WrappedTimestamp, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, is_promise_success, near_bindgen, AccountId, Balance, Promise};
+
+use crate::proposals::ext_self;
+use crate::types::GAS_FOR_PAYMENT_CALLBACK;
+use crate::*;
+
+/// Payment of a fixed amount that is due at the end of every interval.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, PartialEq)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+#[serde(crate = "near_sdk::serde")]
+pub struct RecurringPayment {
+    /// Token the payments are made in.
+    pub token_id: AccountId,
+    /// Account that claims the payments.
+    pub receiver_id: AccountId,
+    /// Amount of each payment.
+    pub amount: U128,
+    /// Time between payments.
+    pub interval: WrappedDuration,
+    /// Time after which no more payments fall due.
+    #[serde(default)]
+    pub end: Option<WrappedTimestamp>,
+    /// Most payments to make.
+    #[serde(default)]
+    pub count: Option<u32>,
+}
+
+impl RecurringPayment {
+    pub fn assert_valid(&self) {
+        assert!(self.interval.0 > 0, "ERR_ZERO_INTERVAL");
+        assert!(
+            self.end.is_some() || self.count.is_some(),
+            "ERR_UNBOUNDED_PAYMENT"
+        );
+        self.total(env::block_timestamp()).expect("ERR_PAYMENT_OVERFLOW");
+    }
+
+    /// Returns the most payments that can fall due when starting at the given time.
+    pub fn max_payments(&self, start: u64) -> u32 {
+        let count = self.count.unwrap_or(u32::MAX);
+        match self.end {
+            Some(end) => {
+                let intervals = end.0.saturating_sub(start) / self.interval.0;
+                std::cmp::min(std::cmp::min(intervals, u32::MAX as u64) as u32, count)
+            }
+            None => count,
+        }
+    }
+
+    /// Returns the amount of all payments when starting at the given time, or None
+    /// if it overflows.
+    pub fn total(&self, start: u64) -> Option<Balance> {
+        self.amount.0.checked_mul(self.max_payments(start) as u128)
+    }
+}
+
+/// Recurring payment set up by a proposal, with the payments claimed so far.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct PaymentStream {
+    #[serde(flatten)]
+    pub payment: RecurringPayment,
+    /// Time the payments started from.
+    pub start: WrappedTimestamp,
+    /// Number of payments claimed.
+    pub claimed: u32,
+}
+
+impl PaymentStream {
+    /// Returns the number of payments that fell due by the given time, claimed or not.
+    pub fn due(&self, timestamp: u64) -> u32 {
+        let until = match self.payment.end {
+            Some(end) => std::cmp::min(end.0, timestamp),
+            None => timestamp,
+        };
+        let due = until.saturating_sub(self.start.0) / self.payment.interval.0;
+        let due = std::cmp::min(due, u32::MAX as u64) as u32;
+        match self.payment.count {
+            Some(count) => std::cmp::min(due, count),
+            None => due,
+        }
+    }
+}
+
+impl PaymentStream {
+    /// Returns the amount of the payments that are not claimed yet, due or not.
+    pub fn unclaimed_amount(&self) -> Balance {
+        let remaining = self.payment.max_payments(self.start.0) - self.claimed;
+        self.payment.amount.0 * remaining as u128
+    }
+}
+
+impl Contract {
+    /// Sets up a recurring payment starting now, reserving the amount of all its
+    /// payments, and returns its id.
+    pub(crate) fn internal_create_payment(&mut self, payment: &RecurringPayment) -> u64 {
+        let total = payment
+            .total(env::block_timestamp())
+            .expect("ERR_PAYMENT_OVERFLOW");
+        self.internal_reserve(&payment.token_id, total);
+        let id = self.last_payment_id;
+        self.payments.insert(
+            &id,
+            &PaymentStream {
+                payment: payment.clone(),
+                start: WrappedTimestamp::from(env::block_timestamp()),
+                claimed: 0,
+            },
+        );
+        self.last_payment_id += 1;
+        id
+    }
+
+    /// Stops a recurring payment and returns the funds of the payments that won't
+    /// fall due to the treasury. The payments that are due already can still be claimed.
+    pub(crate) fn internal_cancel_payment(&mut self, id: u64) {
+        let mut stream = self.payments.get(&id).expect("ERR_NO_PAYMENT");
+        let unclaimed = stream.unclaimed_amount();
+        let now = env::block_timestamp();
+        stream.payment.end = Some(WrappedTimestamp::from(match stream.payment.end {
+            Some(end) => std::cmp::min(end.0, now),
+            None => now,
+        }));
+        self.internal_unreserve(
+            &stream.payment.token_id,
+            unclaimed - stream.unclaimed_amount(),
+        );
+        self.payments.insert(&id, &stream);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Claim the payments of a recurring payment that are due. Only the receiver can claim.
+    pub fn claim_payment(&mut self, id: u64) -> Promise {
+        let mut stream = self.payments.get(&id).expect("ERR_NO_PAYMENT");
+        assert_eq!(
+            stream.payment.receiver_id,
+            env::predecessor_account_id(),
+            "ERR_NOT_PAYMENT_RECEIVER"
+        );
+        let count = stream.due(env::block_timestamp()) - stream.claimed;
+        assert!(count > 0, "ERR_NO_PAYMENT_DUE");
+        stream.claimed += count;
+        self.payments.insert(&id, &stream);
+        let amount = stream.payment.amount.0 * count as u128;
+        self.internal_unreserve(&stream.payment.token_id, amount);
+        self.internal_payout(&stream.payment.token_id, &stream.payment.receiver_id, amount)
+        .expect("ERR_NOT_ENOUGH_FUNDS")
+        .then(ext_self::on_payment_claimed(
+            id,
+            count,
+            &env::current_account_id(),
+            0,
+            GAS_FOR_PAYMENT_CALLBACK,
+        ))
+    }
+
+    /// Makes the payments claimable again if paying them out failed.
+    #[private]
+    pub fn on_payment_claimed(&mut self, id: u64, count: u32) {
+        if !is_promise_success() {
+            let mut stream = self.payments.get(&id).expect("ERR_NO_PAYMENT");
+            stream.claimed -= count;
+            self.payments.insert(&id, &stream);
+            self.internal_reserve(
+                &stream.payment.token_id,
+                stream.payment.amount.0 * count as u128,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::json_types::WrappedDuration;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, MockedBlockchain, PromiseResult};
+    use near_sdk_sim::to_yocto;
+
+    use crate::proposals::InstructionOutcome;
+    use crate::test_utils::*;
+    use crate::types::BASE_TOKEN;
+
+    use super::*;
+
+    fn recurring_payment(count: Option<u32>) -> RecurringPayment {
+        RecurringPayment {
+            token_id: BASE_TOKEN.to_string(),
+            receiver_id: accounts(2).into(),
+            amount: U128(to_yocto("1")),
+            interval: WrappedDuration::from(10),
+            end: None,
+            count,
+        }
+    }
+
+    fn payment_contract(context: &mut VMContextBuilder) -> Contract {
+        let mut contract = new_contract(
+            context,
+            VersionedPolicy::Default(vec![accounts(1).into()]),
+        );
+        testing_env!(context.attached_deposit(to_yocto("1")).build());
+        let id = contract.propose(
+            "payroll".to_string(),
+            vec![Instruction::CreateRecurringPayment {
+                payment: recurring_payment(Some(5)),
+            }],
+        );
+        contract.approve(id, 0);
+        contract
+    }
+
+    #[test]
+    fn test_recurring_payment() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = payment_contract(&mut context);
+        let reserved = |contract: &Contract| contract.get_reserved_amount(BASE_TOKEN.to_string()).0;
+        assert_eq!(contract.get_last_payment_id(), 1);
+        assert_eq!(contract.get_payment(0).claimable, 0);
+        assert_eq!(reserved(&contract), to_yocto("5"));
+
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .block_timestamp(25)
+            .build());
+        assert_eq!(contract.get_payment(0).claimable, 2);
+        contract.claim_payment(0);
+        assert_eq!(contract.get_payment(0).payment.claimed, 2);
+
+        // failed payout makes the payments claimable again.
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_payment_claimed(0, 2);
+        assert_eq!(contract.get_payment(0).payment.claimed, 0);
+        assert_eq!(reserved(&contract), to_yocto("5"));
+
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.claim_payment(0);
+        assert_eq!(contract.get_payment(0).payment.claimed, 2);
+        assert_eq!(reserved(&contract), to_yocto("3"));
+
+        // cancelled payment still pays what fell due before.
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .block_timestamp(35)
+            .build());
+        let id = contract.propose(
+            "stop payroll".to_string(),
+            vec![Instruction::CancelRecurringPayment { payment_id: 0 }],
+        );
+        contract.approve(id, 0);
+        // only the payment that fell due stays reserved.
+        assert_eq!(reserved(&contract), to_yocto("1"));
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .block_timestamp(100)
+            .build());
+        assert_eq!(contract.get_payment(0).claimable, 1);
+        contract.claim_payment(0);
+        assert_eq!(contract.get_payment(0).payment.claimed, 3);
+        assert_eq!(contract.get_payments(0, 10)[0].claimable, 0);
+        assert_eq!(reserved(&contract), 0);
+    }
+
+    #[test]
+    fn test_recurring_payment_not_covered() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = payment_contract(&mut context);
+        let mut payment = recurring_payment(Some(1_000));
+        payment.receiver_id = accounts(3).into();
+        let id = contract.propose(
+            "payroll".to_string(),
+            vec![Instruction::CreateRecurringPayment { payment }],
+        );
+        contract.approve(id, 0);
+        let proposal = contract.get_proposal(id).proposal;
+        assert_eq!(proposal.execution[0].outcome, InstructionOutcome::Failed);
+        assert_eq!(contract.get_last_payment_id(), 1);
+    }
+
+    /// Proposes a recurring payment in a fungible token and approves it, leaving it
+    /// pending on the balance check of the token.
+    fn propose_ft_payment(context: &mut VMContextBuilder, contract: &mut Contract) -> u64 {
+        let mut payment = recurring_payment(Some(5));
+        payment.token_id = accounts(4).into();
+        let id = contract.propose(
+            "payroll".to_string(),
+            vec![Instruction::CreateRecurringPayment { payment }],
+        );
+        contract.approve(id, 0);
+        let proposal = contract.get_proposal(id).proposal;
+        assert_eq!(proposal.execution[0].outcome, InstructionOutcome::Pending);
+        assert_eq!(contract.get_last_payment_id(), 1);
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(
+                format!("\"{}\"", to_yocto("5")).into_bytes()
+            )]
+        );
+        id
+    }
+
+    #[test]
+    fn test_ft_recurring_payment() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = payment_contract(&mut context);
+        let id = propose_ft_payment(&mut context, &mut contract);
+        contract.on_reserve_balance(id, 0);
+        assert_eq!(contract.get_last_payment_id(), 2);
+        assert_eq!(contract.get_reserved_amount(accounts(4).into()).0, to_yocto("5"));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_ENOUGH_FUNDS")]
+    fn test_ft_recurring_payment_not_covered() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = payment_contract(&mut context);
+        // the funds reserved already are left out of the balance that covers the payment.
+        contract.internal_reserve(&accounts(4).into(), 1);
+        let id = propose_ft_payment(&mut context, &mut contract);
+        contract.on_reserve_balance(id, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NO_PAYMENT")]
+    fn test_cancel_unknown_payment() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = payment_contract(&mut context);
+        contract.propose(
+            "stop payroll".to_string(),
+            vec![Instruction::CancelRecurringPayment { payment_id: 1 }],
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_PAYMENT_OVERFLOW")]
+    fn test_recurring_payment_overflow() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = payment_contract(&mut context);
+        let mut payment = recurring_payment(Some(u32::MAX));
+        payment.amount = U128(u128::MAX / 2);
+        contract.propose(
+            "payroll".to_string(),
+            vec![Instruction::CreateRecurringPayment { payment }],
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NO_PAYMENT_DUE")]
+    fn test_recurring_payment_not_due() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = payment_contract(&mut context);
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .block_timestamp(5)
+            .build());
+        contract.claim_payment(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_UNBOUNDED_PAYMENT")]
+    fn test_unbounded_recurring_payment() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = payment_contract(&mut context);
+        contract.propose(
+            "payroll".to_string(),
+            vec![Instruction::CreateRecurringPayment {
+                payment: recurring_payment(None),
+            }],
+        );
+    }
+}
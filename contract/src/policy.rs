@@ -6,7 +6,10 @@ use near_sdk::json_types::{WrappedDuration, U128};
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{env, AccountId, Balance};
 
-use crate::proposals::{Proposal, ProposalKind, Instruction, ProposalStatus};
+use crate::proposals::{
+    Instruction, Proposal, ProposalKind, ProposalKindV1, ProposalPhase, ProposalStatus,
+};
+use crate::templates::ProposalTemplate;
 use crate::types::Action;
 
 #[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
@@ -110,6 +113,170 @@ pub enum WeightKind {
     TokenWeight,
     /// Weight of the group role. Roles that don't have scoped group are not supported.
     RoleWeight,
+    /// Using the square root of token amounts at proposal submission, against the sum
+    /// of the square roots of all delegations at that time. Dampens the influence of
+    /// large holders.
+    Quadratic,
+    /// Using the power of tokens locked with `lock`, which scales with the lock time
+    /// remaining at proposal submission, against the total power of all locks at that time.
+    VoteEscrow,
+}
+
+/// How voters express their preference between the versions of a proposal.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+#[serde(crate = "near_sdk::serde")]
+pub enum BallotKind {
+    /// Voters back a single version. The first version to reach the threshold passes.
+    #[default]
+    Single,
+    /// Voters rank the versions in order of preference. The winning version is decided
+    /// by an instant-runoff tally once the proposal period is over.
+    Ranked,
+    /// Voters approve any subset of the versions. Each approved version is credited
+    /// with the full weight of the vote.
+    Approval,
+}
+
+/// Configuration of conviction voting. Instead of approving, members stake their
+/// delegated tokens behind a proposal version. The conviction of a version builds
+/// up toward the amount staked behind it and decays once stakes are withdrawn,
+/// halving the distance to the staked amount every half life. A version passes once
+/// its conviction crosses a threshold that grows with the share of the treasury it requests.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct ConvictionPolicy {
+    /// Time it takes for conviction to cover half of the distance to the staked amount.
+    pub half_life: WrappedDuration,
+    /// Conviction required from the total delegated supply by a proposal requesting nothing.
+    pub min_threshold: WeightOrRatio,
+    /// Most of the available treasury that a single proposal can request.
+    pub spending_limit: WeightOrRatio,
+}
+
+/// `2^(-1/2^i)` for `i` from 1 to 20, as fractions of `2^64`.
+const HALVING_ROOTS: [u128; 20] = [
+    13_043_817_825_332_782_212,
+    15_511_800_964_685_064_948,
+    16_915_738_899_553_466_670,
+    17_664_662_643_191_237_676,
+    18_051_468_387_014_017_850,
+    18_248_035_989_933_441_396,
+    18_347_121_020_861_646_923,
+    18_396_865_112_328_554_661,
+    18_421_787_711_448_657_617,
+    18_434_261_669_329_232_139,
+    18_440_501_815_349_552_981,
+    18_443_622_680_442_407_997,
+    18_445_183_311_048_607_332,
+    18_445_963_675_871_538_003,
+    18_446_353_870_663_572_145,
+    18_446_548_971_154_807_802,
+    18_446_646_522_174_239_825,
+    18_446_695_297_877_410_579,
+    18_446_719_685_777_359_790,
+    18_446_731_879_739_425_374,
+];
+
+impl ConvictionPolicy {
+    /// Returns the conviction reached after `elapsed` time from `conviction` with
+    /// `staked` tokens behind it.
+    pub fn conviction(&self, conviction: Balance, staked: Balance, elapsed: u64) -> Balance {
+        if conviction <= staked {
+            staked - self.decay(staked - conviction, elapsed)
+        } else {
+            staked + self.decay(conviction - staked, elapsed)
+        }
+    }
+
+    /// Returns what is left of `amount` after halving it every half life for `elapsed` time.
+    fn decay(&self, amount: Balance, elapsed: u64) -> Balance {
+        let half_life = self.half_life.0;
+        if half_life == 0 || elapsed / half_life >= 128 {
+            return 0;
+        }
+        let mut amount = amount >> (elapsed / half_life);
+        // Remaining fraction of a half life, in 2^-20ths.
+        let fraction = (elapsed % half_life) as u128 * (1 << 20) / half_life as u128;
+        for (i, root) in HALVING_ROOTS.iter().enumerate() {
+            if fraction & (1 << (19 - i)) != 0 {
+                amount = mul_div(amount, *root, 1 << 64);
+            }
+        }
+        amount
+    }
+
+    /// Returns the conviction needed by a proposal requesting `requested` out of the
+    /// `available` treasury, or None if it requests more than the spending limit.
+    /// The threshold is `min_threshold / (1 - requested / limit)^2`.
+    pub fn threshold(&self, requested: Balance, available: Balance, total_supply: Balance) -> Option<Balance> {
+        let limit = self.spending_limit.to_weight(available);
+        if requested >= limit {
+            return None;
+        }
+        let min_threshold = self.min_threshold.to_weight(total_supply);
+        // Scale both terms of the ratio down to 64 bits to avoid overflows.
+        let shift = (128 - limit.leading_zeros()).saturating_sub(64);
+        let remaining = std::cmp::max((limit - requested) >> shift, 1);
+        let limit = limit >> shift;
+        Some(mul_div(mul_div(min_threshold, limit, remaining), limit, remaining))
+    }
+}
+
+/// Computes `a * b / c` for `b` and `c` up to `2^64`, saturating on overflow.
+pub(crate) fn mul_div(a: u128, b: u128, c: u128) -> u128 {
+    (a / c)
+        .saturating_mul(b)
+        .saturating_add((a % c) * b / c)
+}
+
+/// How the quorum of a vote policy is applied.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Default)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub enum QuorumKind {
+    /// The `quorum` field is a floor on the weight needed by a version to pass.
+    #[default]
+    Legacy,
+    /// Minimum turnout of approvals, rejections and abstentions together. Once reached,
+    /// a version passes with the threshold ratio of the votes actually cast, abstentions
+    /// included, when the voting ends, or earlier with the threshold ratio of the total weight.
+    Participation(WeightOrRatio),
+}
+
+/// What happens to committed votes that are not revealed by the end of the reveal period.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub enum UnrevealedVotes {
+    /// Unrevealed votes are not counted.
+    Ignore,
+    /// Unrevealed votes are counted as abstentions.
+    Abstain,
+}
+
+/// Commit-reveal voting. During the proposal period voters only commit to the hash
+/// of their vote, which they reveal in the reveal period that follows.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct SecretBallot {
+    /// Time after the proposal period in which votes can be revealed.
+    pub reveal_period: WrappedDuration,
+    /// How votes that were not revealed are counted.
+    pub unrevealed: UnrevealedVotes,
+}
+
+/// Role whose members have to approve proposals on their own under bicameral votes.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct House {
+    /// Name of the role.
+    pub role: String,
+    /// How many votes of the role's members are needed to decide.
+    pub threshold: WeightOrRatio,
 }
 
 /// Defines configuration of the vote.
@@ -127,6 +294,69 @@ pub struct VotePolicy {
     pub quorum: U128,
     /// How many votes to pass this vote.
     pub threshold: WeightOrRatio,
+    /// Kind of ballot voters cast.
+    #[serde(default)]
+    pub ballot_kind: BallotKind,
+    /// If set, proposals are passed by conviction voting instead of approvals and
+    /// don't expire.
+    #[serde(default)]
+    pub conviction: Option<ConvictionPolicy>,
+    /// If set, proposals follow lazy consensus: when the proposal period expires
+    /// without a decision, the proposal is approved unless the weight of rejections
+    /// reached this objection threshold.
+    #[serde(default)]
+    pub objection_threshold: Option<WeightOrRatio>,
+    /// How the quorum is applied.
+    #[serde(default)]
+    pub quorum_kind: QuorumKind,
+    /// If set, votes are committed as hashes and only tallied once revealed.
+    #[serde(default)]
+    pub secret_ballot: Option<SecretBallot>,
+    /// If not empty, a version only passes once the approvals of the members of every
+    /// house reach the house's threshold, and the proposal is rejected once the
+    /// rejections of any house do. Replaces the combined threshold.
+    #[serde(default)]
+    pub houses: Vec<House>,
+}
+
+impl VotePolicy {
+    /// Returns the weight of a vote cast by a user with the given delegated amount.
+    pub fn vote_weight(&self, user_weight: Balance) -> Balance {
+        match self.weight_kind {
+            WeightKind::TokenWeight => user_weight,
+            WeightKind::RoleWeight => 1,
+            WeightKind::Quadratic => integer_sqrt(user_weight),
+            WeightKind::VoteEscrow => user_weight,
+        }
+    }
+
+    /// Returns the weight needed by a version to pass or by the rejections to reject
+    /// the proposal, given the total weight and the turnout of the proposal.
+    /// Returns None while a participation quorum hasn't been reached. Before the
+    /// voting is `closed`, a participation threshold is taken over the total weight,
+    /// so that the votes still to come can't change the decision.
+    pub fn decision_threshold(
+        &self,
+        total_weight: Balance,
+        turnout: Balance,
+        closed: bool,
+    ) -> Option<Balance> {
+        match &self.quorum_kind {
+            QuorumKind::Legacy => Some(std::cmp::max(
+                self.quorum.0,
+                self.threshold.to_weight(total_weight),
+            )),
+            QuorumKind::Participation(quorum) => {
+                if turnout < quorum.to_weight(total_weight) {
+                    None
+                } else if closed {
+                    Some(self.threshold.to_weight(turnout))
+                } else {
+                    Some(self.threshold.to_weight(total_weight))
+                }
+            }
+        }
+    }
 }
 
 impl Default for VotePolicy {
@@ -135,6 +365,12 @@ impl Default for VotePolicy {
             weight_kind: WeightKind::RoleWeight,
             quorum: U128(0),
             threshold: WeightOrRatio::Ratio(1, 2),
+            ballot_kind: BallotKind::Single,
+            conviction: None,
+            objection_threshold: None,
+            quorum_kind: QuorumKind::Legacy,
+            secret_ballot: None,
+            houses: vec![],
         }
     }
 }
@@ -160,15 +396,83 @@ pub struct Policy {
     pub bounty_bond: U128,
     /// Period in which giving up on bounty is not punished.
     pub bounty_forgiveness_period: WrappedDuration,
+    /// Longest time delegated tokens can be locked for vote escrow. A lock of this
+    /// duration carries the full weight of its tokens. Fixed once anything is locked.
+    #[serde(default = "default_max_lock_duration")]
+    pub max_lock_duration: WrappedDuration,
+    /// Templates members can propose from.
+    #[serde(default)]
+    pub templates: Vec<ProposalTemplate>,
+    /// DAOs allowed to move their proposals to this one with `move_to_hub`.
+    #[serde(default)]
+    pub sub_daos: Vec<AccountId>,
+}
+
+fn default_max_lock_duration() -> WrappedDuration {
+    WrappedDuration::from(1_000_000_000 * 60 * 60 * 24 * 365 * 4)
+}
+
+/// Vote policy as stored before ballot kinds were introduced.
+#[derive(BorshSerialize, BorshDeserialize, Clone)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct VotePolicyV1 {
+    pub weight_kind: WeightKind,
+    pub quorum: U128,
+    pub threshold: WeightOrRatio,
+}
+
+impl From<VotePolicyV1> for VotePolicy {
+    fn from(v: VotePolicyV1) -> Self {
+        VotePolicy {
+            weight_kind: v.weight_kind,
+            quorum: v.quorum,
+            threshold: v.threshold,
+            ..VotePolicy::default()
+        }
+    }
+}
+
+/// Policy as stored before ballot kinds were introduced.
+#[derive(BorshSerialize, BorshDeserialize, Clone)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct PolicyV1 {
+    pub proposal_kinds: Vec<ProposalKindV1>,
+    pub roles: Vec<RolePermission>,
+    pub default_vote_policy: VotePolicyV1,
+    pub proposal_bond: U128,
+    pub proposal_period: WrappedDuration,
+    pub bounty_bond: U128,
+    pub bounty_forgiveness_period: WrappedDuration,
 }
 
-/// Versioned policy.
+impl From<PolicyV1> for Policy {
+    fn from(v: PolicyV1) -> Self {
+        Policy {
+            proposal_kinds: v.proposal_kinds.into_iter().map(|kind| kind.into()).collect(),
+            roles: v.roles,
+            default_vote_policy: v.default_vote_policy.into(),
+            proposal_bond: v.proposal_bond,
+            proposal_period: v.proposal_period,
+            bounty_bond: v.bounty_bond,
+            bounty_forgiveness_period: v.bounty_forgiveness_period,
+            max_lock_duration: default_max_lock_duration(),
+            templates: vec![],
+            sub_daos: vec![],
+        }
+    }
+}
+
+/// Versioned policy. Policies stored before ballot kinds were introduced are read
+/// as `V1` and upgraded into the latest policy.
 #[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
 #[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
 #[serde(crate = "near_sdk::serde", untagged)]
+#[allow(clippy::large_enum_variant)]
 pub enum VersionedPolicy {
     /// Default policy with given accounts as council.
     Default(Vec<AccountId>),
+    #[serde(skip)]
+    V1(PolicyV1),
     Current(Policy),
 }
 
@@ -195,8 +499,12 @@ fn default_policy(council: Vec<AccountId>) -> Policy {
                     "*:AddProposal".to_string(),
                     "*:VoteApprove".to_string(),
                     "*:VoteReject".to_string(),
+                    "*:VoteAbstain".to_string(),
                     "*:VoteRemove".to_string(),
                     "*:Finalize".to_string(),
+                    "*:CancelProposal".to_string(),
+                    "*:RetryExecution".to_string(),
+                    "*:MoveToHub".to_string(),
                 ]
                 .into_iter()
                 .collect(),
@@ -207,6 +515,9 @@ fn default_policy(council: Vec<AccountId>) -> Policy {
         proposal_period: WrappedDuration::from(1_000_000_000 * 60 * 60 * 24 * 7),
         bounty_bond: U128(10u128.pow(24)),
         bounty_forgiveness_period: WrappedDuration::from(1_000_000_000 * 60 * 60 * 24),
+        max_lock_duration: default_max_lock_duration(),
+        templates: vec![],
+        sub_daos: vec![],
     }
 }
 
@@ -217,6 +528,7 @@ impl VersionedPolicy {
             VersionedPolicy::Default(accounts) => {
                 VersionedPolicy::Current(default_policy(accounts))
             }
+            VersionedPolicy::V1(policy) => VersionedPolicy::Current(policy.into()),
             VersionedPolicy::Current(policy) => VersionedPolicy::Current(policy),
         }
     }
@@ -224,6 +536,7 @@ impl VersionedPolicy {
     /// Return recent version of policy.
     pub fn to_policy(self) -> Policy {
         match self {
+            VersionedPolicy::V1(policy) => policy.into(),
             VersionedPolicy::Current(policy) => policy,
             _ => unimplemented!(),
         }
@@ -292,6 +605,66 @@ impl Policy {
         None
     }
 
+    /// Returns the proposal period of the given proposal kind.
+    pub fn get_proposal_period(&self, proposal_kind: &String) -> u64 {
+        self.proposal_kinds
+            .iter()
+            .find(|p| p.name == *proposal_kind)
+            .and_then(|p| p.proposal_period)
+            .unwrap_or(self.proposal_period)
+            .0
+    }
+
+    /// Returns the proposal bond of the given proposal kind.
+    pub fn get_proposal_bond(&self, proposal_kind: &String) -> Balance {
+        self.proposal_kinds
+            .iter()
+            .find(|p| p.name == *proposal_kind)
+            .and_then(|p| p.proposal_bond)
+            .unwrap_or(self.proposal_bond)
+            .0
+    }
+
+    /// Returns the most versions a proposal of the given kind can have, if limited.
+    pub fn get_max_versions(&self, proposal_kind: &String) -> Option<u8> {
+        self.proposal_kinds
+            .iter()
+            .find(|p| p.name == *proposal_kind)
+            .and_then(|p| p.max_versions)
+    }
+
+    /// Asserts that the templates of the policy are well formed and have distinct names.
+    pub fn assert_valid_templates(&self) {
+        for (i, template) in self.templates.iter().enumerate() {
+            assert!(
+                self.templates[..i].iter().all(|t| t.name != template.name),
+                "ERR_DUPLICATE_TEMPLATE"
+            );
+            template.assert_valid(self);
+        }
+    }
+
+    /// Returns the template with the given name, if any.
+    pub fn get_template(&self, name: &str) -> Option<&ProposalTemplate> {
+        self.templates.iter().find(|t| t.name == name)
+    }
+
+    /// Returns the discussion period of the given proposal kind, if any.
+    pub fn get_discussion_period(&self, proposal_kind: &String) -> Option<WrappedDuration> {
+        self.proposal_kinds
+            .iter()
+            .find(|p| p.name == *proposal_kind)
+            .and_then(|p| p.discussion_period)
+    }
+
+    /// Returns the execution delay of the given proposal kind, if any.
+    pub fn get_execution_delay(&self, proposal_kind: &String) -> Option<WrappedDuration> {
+        self.proposal_kinds
+            .iter()
+            .find(|p| p.name == *proposal_kind)
+            .and_then(|p| p.execution_delay)
+    }
+
     /// Returns the kind of proposal based off the instructions within the proposal. 
     /// Returns an empty string if no policies match
     pub fn match_proposal_kind(&self, instructions: &Vec<Instruction>) -> String {
@@ -327,37 +700,134 @@ impl Policy {
         false
     }
 
+    /// Returns true if the given user is member of a role that can vote on some
+    /// proposal kind.
+    pub fn can_vote(&self, user: UserInfo) -> bool {
+        let labels: Vec<String> = [Action::VoteApprove, Action::VoteReject, Action::VoteAbstain]
+            .iter()
+            .map(|action| action.to_label())
+            .collect();
+        self.get_user_roles(user).values().any(|permissions| {
+            permissions.iter().any(|permission| {
+                let action = permission.split(':').nth(1).unwrap_or_default();
+                action == "*" || labels.iter().any(|label| label == action)
+            })
+        })
+    }
+
     /// Get proposal status for given proposal.
     /// Usually is called after changing it's state.
     pub fn proposal_status(
         &self,
         proposal: &Proposal,
         total_supply: Balance,
+        member_weight: impl Fn(&AccountId) -> Balance,
     ) -> ProposalStatus {
         assert_eq!(
             proposal.status,
             ProposalStatus::InProgress,
             "ERR_PROPOSAL_NOT_IN_PROGRESS"
         );
-        if proposal.submission_time.0 + self.proposal_period.0 < env::block_timestamp() {
-            // Proposal expired.
-            return ProposalStatus::Expired;
-        };
         let vote_policy = self
             .get_vote_policy(&proposal.kind)
             .unwrap_or(&self.default_vote_policy);
-        let threshold = self.get_threshold(&vote_policy, total_supply, &proposal.kind);
+        let total = self.get_total_weight(vote_policy, total_supply, &proposal.kind);
+        let closed = self.proposal_phase(proposal) == ProposalPhase::Closed;
+        let threshold = vote_policy.decision_threshold(total, proposal.turnout(), closed);
+        let house_thresholds =
+            self.house_thresholds(vote_policy, total_supply, &proposal.kind, member_weight);
+        if vote_policy.conviction.is_some() {
+            // Conviction is evaluated against the treasury by the contract.
+            match threshold {
+                Some(threshold) if proposal.reject_count > threshold => {
+                    return ProposalStatus::Rejected
+                }
+                _ => return proposal.status.clone(),
+            }
+        }
+        if closed {
+            // Proposal expired. Ranked ballots are only tallied at this point.
+            if let (BallotKind::Ranked, Some(threshold)) = (&vote_policy.ballot_kind, threshold) {
+                if let Some(version) = proposal.instant_runoff(threshold) {
+                    return ProposalStatus::Approved{ version }
+                }
+            }
+            if !house_thresholds.is_empty() {
+                if let Some(status) = proposal.house_status(&house_thresholds) {
+                    return status
+                }
+            }
+            // Secret ballots are tallied now that all votes are revealed, and
+            // participation quorums now that the turnout is final.
+            let final_tally = vote_policy.secret_ballot.is_some()
+                || matches!(vote_policy.quorum_kind, QuorumKind::Participation(_));
+            if let (true, Some(threshold)) = (final_tally, threshold) {
+                if proposal.reject_count > threshold {
+                    return ProposalStatus::Rejected
+                }
+                if vote_policy.ballot_kind != BallotKind::Ranked {
+                    if let Some(version) = proposal.leading_version(threshold) {
+                        return ProposalStatus::Approved{ version }
+                    }
+                }
+            }
+            if let Some(objection_threshold) = &vote_policy.objection_threshold {
+                if proposal.reject_count >= objection_threshold.to_weight(total) {
+                    return ProposalStatus::Rejected
+                }
+                if let Some(version) = proposal.preferred_version() {
+                    return ProposalStatus::Approved{ version }
+                }
+            }
+            return ProposalStatus::Expired;
+        };
+        if vote_policy.secret_ballot.is_some() {
+            // Secret ballots are only tallied once the reveal period is over.
+            return proposal.status.clone()
+        }
+        if !house_thresholds.is_empty() {
+            return proposal
+                .house_status(&house_thresholds)
+                .unwrap_or_else(|| proposal.status.clone())
+        }
+        let threshold = match threshold {
+            Some(threshold) => threshold,
+            None => return proposal.status.clone(),
+        };
         if proposal.reject_count > threshold {
             return ProposalStatus::Rejected
         }
-        let mut version = 0;
-        for total in proposal.approve_count.clone().into_iter() {
-            if total >= threshold {
-                return ProposalStatus::Approved{ version }
+        if vote_policy.ballot_kind == BallotKind::Ranked {
+            return proposal.status.clone()
+        }
+        match proposal.leading_version(threshold) {
+            Some(version) => ProposalStatus::Approved{ version },
+            None => proposal.status.clone(),
+        }
+    }
+
+    /// Returns the phase of the voting on the given proposal at the current time.
+    pub fn proposal_phase(&self, proposal: &Proposal) -> ProposalPhase {
+        let vote_policy = self
+            .get_vote_policy(&proposal.kind)
+            .unwrap_or(&self.default_vote_policy);
+        let now = env::block_timestamp();
+        if now < proposal.voting_start.0 {
+            return ProposalPhase::Discussion
+        }
+        if vote_policy.conviction.is_some() {
+            return ProposalPhase::Voting
+        }
+        let voting_end = proposal.voting_start.0 + self.get_proposal_period(&proposal.kind);
+        if now <= voting_end {
+            return ProposalPhase::Voting
+        }
+        match &vote_policy.secret_ballot {
+            Some(secret_ballot) if now <= voting_end + secret_ballot.reveal_period.0 => {
+                ProposalPhase::Reveal
             }
-            version += 1;
+            _ => ProposalPhase::Closed,
         }
-        proposal.status.clone()
     }
 
     /// Calculates the threshold number of weighted vote needed
@@ -365,24 +835,92 @@ impl Policy {
     pub fn get_threshold(&self, vote_policy: &VotePolicy, total_supply: u128, proposal_kind: &String) -> u128 {
         std::cmp::max(
             vote_policy.quorum.0,
-            match &vote_policy.weight_kind {
-                WeightKind::TokenWeight => vote_policy.threshold.to_weight(total_supply),
-                WeightKind::RoleWeight => {
-                    let mut total: u128 = 0;
-                    for role in self.roles.iter() {
-                        if role.permissions.contains(&format!("{}:*", proposal_kind))
-                            || role.permissions.contains(&format!("*:{}", Action::VoteApprove.to_label()))
-                            || role.permissions.contains(&format!("{}:{}", proposal_kind, Action::VoteApprove.to_label())) {
-                            total += role
-                                .kind
-                                .get_role_size()
-                                .expect("ERR_UNSUPPORTED_ROLE") as Balance
-                        }
+            vote_policy.threshold.to_weight(
+                self.get_total_weight(vote_policy, total_supply, proposal_kind)
+            ),
+        )
+    }
+
+    /// Returns the weight each house of the vote policy needs to decide a proposal.
+    /// Thresholds of group houses are computed over the vote weight of their members,
+    /// given by `member_weight`, and those of other houses over the total weight.
+    pub fn house_thresholds(
+        &self,
+        vote_policy: &VotePolicy,
+        total_supply: Balance,
+        proposal_kind: &String,
+        member_weight: impl Fn(&AccountId) -> Balance,
+    ) -> Vec<(String, Balance)> {
+        vote_policy
+            .houses
+            .iter()
+            .map(|house| {
+                let role = self
+                    .roles
+                    .iter()
+                    .find(|role| role.name == house.role)
+                    .expect("ERR_NO_HOUSE_ROLE");
+                let total = match (&vote_policy.weight_kind, &role.kind) {
+                    (WeightKind::RoleWeight, kind) => {
+                        kind.get_role_size().expect("ERR_UNSUPPORTED_ROLE") as Balance
                     }
-                    vote_policy.threshold.to_weight(total)
-                },
+                    (_, RoleKind::Group(members)) => members.iter().map(&member_weight).sum(),
+                    _ => self.get_total_weight(vote_policy, total_supply, proposal_kind),
+                };
+                (house.role.clone(), house.threshold.to_weight(total))
+            })
+            .collect()
+    }
+
+    /// Returns the houses of the vote policy the user is a member of.
+    pub fn user_houses(&self, vote_policy: &VotePolicy, user: UserInfo) -> Vec<String> {
+        let roles = self.get_user_roles(user);
+        vote_policy
+            .houses
+            .iter()
+            .filter(|house| roles.contains_key(&house.role))
+            .map(|house| house.role.clone())
+            .collect()
+    }
+
+    /// Returns the total voting weight that the threshold is computed over.
+    pub fn get_total_weight(&self, vote_policy: &VotePolicy, total_supply: u128, proposal_kind: &String) -> u128 {
+        match &vote_policy.weight_kind {
+            // Under quadratic voting the total supply is already the sum of the square roots.
+            WeightKind::TokenWeight | WeightKind::VoteEscrow | WeightKind::Quadratic => {
+                total_supply
+            }
+            WeightKind::RoleWeight => {
+                let mut total: u128 = 0;
+                for role in self.roles.iter() {
+                    if role.permissions.contains(&format!("{}:*", proposal_kind))
+                        || role.permissions.contains(&format!("*:{}", Action::VoteApprove.to_label()))
+                        || role.permissions.contains(&format!("{}:{}", proposal_kind, Action::VoteApprove.to_label())) {
+                        total += role
+                            .kind
+                            .get_role_size()
+                            .expect("ERR_UNSUPPORTED_ROLE") as Balance
+                    }
+                }
+                total
             },
-        )
+        }
+    }
+}
+
+/// Returns the largest integer whose square is less or equal to `n`.
+pub fn integer_sqrt(n: u128) -> u128 {
+    if n < 2 {
+        return n;
+    }
+    // Newton's method, starting from a power of two above the root.
+    let mut x = 1u128 << ((128 - n.leading_zeros()) / 2 + 1);
+    loop {
+        let y = (x + n / x) / 2;
+        if y >= x {
+            return x;
+        }
+        x = y;
     }
 }
 
@@ -401,4 +939,80 @@ mod tests {
         let r2 = WeightOrRatio::Ratio(1, 1);
         assert_eq!(r2.to_weight(5), 5);
     }
+
+    #[test]
+    fn test_integer_sqrt() {
+        assert_eq!(integer_sqrt(0), 0);
+        assert_eq!(integer_sqrt(1), 1);
+        assert_eq!(integer_sqrt(3), 1);
+        assert_eq!(integer_sqrt(4), 2);
+        assert_eq!(integer_sqrt(99), 9);
+        assert_eq!(integer_sqrt(100), 10);
+        assert_eq!(integer_sqrt(10u128.pow(24)), 10u128.pow(12));
+        assert_eq!(integer_sqrt(u128::MAX), u64::MAX as u128);
+    }
+
+    #[test]
+    fn test_quadratic_threshold() {
+        let policy = default_policy(vec![]);
+        let vote_policy = VotePolicy {
+            weight_kind: WeightKind::Quadratic,
+            quorum: U128(0),
+            threshold: WeightOrRatio::Ratio(1, 2),
+            ..VotePolicy::default()
+        };
+        let kind = "".to_string();
+        // The total is the sum of the square roots, e.g. sqrt(10_000) + 2 * sqrt(100) = 120.
+        assert_eq!(policy.get_total_weight(&vote_policy, 120, &kind), 120);
+        assert_eq!(policy.get_threshold(&vote_policy, 120, &kind), 61);
+    }
+
+    #[test]
+    fn test_participation_quorum() {
+        let legacy = VotePolicy {
+            quorum: U128(60),
+            ..VotePolicy::default()
+        };
+        assert_eq!(legacy.decision_threshold(100, 0, false), Some(60));
+        assert_eq!(legacy.decision_threshold(200, 0, false), Some(101));
+        let participation = VotePolicy {
+            quorum_kind: QuorumKind::Participation(WeightOrRatio::Ratio(1, 4)),
+            ..VotePolicy::default()
+        };
+        // a quarter of 100 is 26.
+        assert_eq!(participation.decision_threshold(100, 25, true), None);
+        assert_eq!(participation.decision_threshold(100, 26, true), Some(14));
+        assert_eq!(participation.decision_threshold(100, 100, true), Some(51));
+        // until the voting is closed, the votes cast must decide over all of the weight.
+        assert_eq!(participation.decision_threshold(100, 25, false), None);
+        assert_eq!(participation.decision_threshold(100, 26, false), Some(51));
+    }
+
+    #[test]
+    fn test_conviction_threshold() {
+        let conviction = ConvictionPolicy {
+            half_life: WrappedDuration::from(100),
+            min_threshold: WeightOrRatio::Weight(U128(1_000)),
+            spending_limit: WeightOrRatio::Ratio(1, 2),
+        };
+        assert_eq!(conviction.conviction(0, 512, 0), 0);
+        assert_eq!(conviction.conviction(0, 512, 100), 256);
+        assert_eq!(conviction.conviction(0, 512, 200), 384);
+        // sqrt(1/2) of the distance is left after half of a half life.
+        assert_eq!(conviction.conviction(0, 1_000_000, 50), 292_894);
+        assert_eq!(conviction.conviction(0, 512, 100_000), 512);
+        // conviction decays once the stake is withdrawn.
+        assert_eq!(conviction.conviction(512, 0, 100), 256);
+        assert_eq!(conviction.conviction(512, 256, 100), 384);
+        // the spending limit is half of the treasury, a bit over 5_000.
+        assert_eq!(conviction.threshold(0, 10_000, 1_000_000), Some(1_000));
+        assert_eq!(conviction.threshold(2_500, 10_000, 1_000_000), Some(3_997));
+        assert_eq!(conviction.threshold(5_001, 10_000, 1_000_000), None);
+        // large balances don't overflow.
+        let near = 10u128.pow(24);
+        assert_eq!(
+            conviction.threshold(2_500 * near, 10_000 * near, 1_000_000),
+            Some(4_000)
+        );
+    }
 }
@@ -3,12 +3,20 @@ use std::u128;
 
 use near_contract_standards::fungible_token::core_impl::ext_fungible_token;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::json_types::{Base64VecU8, WrappedTimestamp, U64};
-use near_sdk::{AccountId, Balance, PromiseOrValue};
+use near_sdk::json_types::{Base64VecU8, WrappedDuration, WrappedTimestamp, U64};
+use near_sdk::json_types::ValidAccountId;
+use near_sdk::{
+    ext_contract, is_promise_success, promise_result_as_success, AccountId, Balance, Gas,
+};
 
-use crate::policy::{UserInfo, WeightKind};
+use crate::commit_reveal::vote_hash;
+use crate::conviction::{Conviction, ConvictionStake};
+use crate::policy::{BallotKind, RoleKind, UserInfo, VotePolicyV1};
 use crate::types::{
-    upgrade_remote, upgrade_self, Action, Config, BASE_TOKEN, GAS_FOR_FT_TRANSFER, ONE_YOCTO_NEAR,
+    upgrade_remote, upgrade_self, Action, Config, BASE_TOKEN, GAS_FOR_ACCEPT_MOVED_PROPOSAL,
+    GAS_FOR_EXECUTION_CALLBACK, GAS_FOR_FT_BALANCE_OF, GAS_FOR_FT_TRANSFER, GAS_FOR_MOVE_CALLBACK,
+    GAS_FOR_PAYOUT_CALLBACK, GAS_FOR_RESERVE_CALLBACK, GAS_FOR_UPGRADE_REMOTE_DEPLOY,
+    ONE_YOCTO_NEAR,
 };
 use crate::*;
 
@@ -28,6 +36,47 @@ pub struct  ProposalKind {
     required_instrs: Vec<InstructionKind>,
     /// the vote policy that get's associated
     pub vote_policy: VotePolicy,
+    /// Overrides the proposal period of the policy for this kind.
+    #[serde(default)]
+    pub proposal_period: Option<WrappedDuration>,
+    /// Overrides the proposal bond of the policy for this kind.
+    #[serde(default)]
+    pub proposal_bond: Option<U128>,
+    /// Most versions a proposal of this kind can have, including the original one.
+    #[serde(default)]
+    pub max_versions: Option<u8>,
+    /// Time after submission in which proposals of this kind can only be countered,
+    /// amended or withdrawn. Voting opens once it ends and versions are frozen.
+    #[serde(default)]
+    pub discussion_period: Option<WrappedDuration>,
+    /// Time approved proposals of this kind are queued for before they can be executed.
+    /// Roles with the `CancelProposal` permission can cancel them in the meantime.
+    #[serde(default)]
+    pub execution_delay: Option<WrappedDuration>,
+}
+
+/// Proposal kind as stored before ballot kinds were introduced.
+#[derive(BorshSerialize, BorshDeserialize, Clone)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct ProposalKindV1 {
+    pub name: String,
+    required_instrs: Vec<InstructionKind>,
+    pub vote_policy: VotePolicyV1,
+}
+
+impl From<ProposalKindV1> for ProposalKind {
+    fn from(v: ProposalKindV1) -> Self {
+        ProposalKind {
+            name: v.name,
+            required_instrs: v.required_instrs,
+            vote_policy: v.vote_policy.into(),
+            proposal_period: None,
+            proposal_bond: None,
+            max_versions: None,
+            discussion_period: None,
+            execution_delay: None,
+        }
+    }
 }
 
 impl ProposalKind {
@@ -48,7 +97,17 @@ impl ProposalKind {
 pub enum ProposalStatus {
     InProgress,
     /// If quorum voted yes, one of the versions of the proposal was successfully approved.
+    /// Stays approved while instructions that run through promises are pending.
     Approved{ version: u8 },
+    /// All instructions of the approved version succeeded.
+    Executed{ version: u8 },
+    /// Some instructions of the approved version failed. These can be run again with `retry`.
+    Failed{ version: u8 },
+    /// Approved and waiting for the execution delay of its kind to pass, and for
+    /// its prerequisites to be met, before the version can be executed.
+    Queued{ version: u8, execute_after: WrappedTimestamp, awaiting_prerequisites: bool },
+    /// Cancelled while queued. Bond is returned.
+    Cancelled,
     /// If quorum voted no, this proposal is rejected. Bond is returned.
     Rejected,
     /// Expired after period of time.
@@ -57,9 +116,55 @@ pub enum ProposalStatus {
     Moved,
 }
 
+/// Outcome of an instruction of an approved proposal.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum InstructionOutcome {
+    /// Waiting for the result of the promise running the instruction.
+    Pending,
+    /// Local instruction of an atomic proposal, applied once all promises succeeded.
+    Staged,
+    /// Staged instruction that was dropped because a promise failed.
+    Reverted,
+    Succeeded,
+    Failed,
+}
+
+/// Instruction of an approved proposal together with its outcome.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+#[serde(crate = "near_sdk::serde")]
+pub struct ExecutedInstruction {
+    /// Instruction as it was run. Bounty payouts are kept as the transfer paying them out.
+    pub instruction: Instruction,
+    pub outcome: InstructionOutcome,
+}
+
+/// Proposal in another DAO.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProposalLink {
+    pub dao_id: AccountId,
+    pub proposal_id: u64,
+}
+
+/// Phase of the voting on a proposal.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum ProposalPhase {
+    /// Versions are discussed, countered and amended. No votes are cast yet.
+    Discussion,
+    /// Votes are cast, or committed for secret ballots.
+    Voting,
+    /// Committed votes of secret ballots are revealed.
+    Reveal,
+    /// Voting is over and the proposal can be finalized.
+    Closed,
+}
+
 /// Function call arguments.
-#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, PartialEq)]
-#[cfg_attr(not(target_arch = "wasm32"), derive(Clone, Debug))]
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, PartialEq, Clone)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
 #[serde(crate = "near_sdk::serde")]
 pub struct ActionCall {
     method_name: String,
@@ -69,9 +174,10 @@ pub struct ActionCall {
 }
 
 /// Instruction is an action that may be executed when a proposal is approved.
-#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
-#[cfg_attr(not(target_arch = "wasm32"), derive(Clone, Debug))]
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
 #[serde(crate = "near_sdk::serde")]
+#[allow(clippy::large_enum_variant)]
 pub enum Instruction {
     /// Change the DAO config.
     ChangeConfig { config: Config },
@@ -112,6 +218,14 @@ pub enum Instruction {
     },
     /// Just a signaling vote, with no execution.
     Vote,
+    /// Set up a payment the receiver can claim every interval.
+    CreateRecurringPayment { payment: RecurringPayment },
+    /// Stop the given recurring payment. Payments already due can still be claimed.
+    CancelRecurringPayment { payment_id: u64 },
+    /// Reserve tokens that vest to the beneficiary over time.
+    CreateVesting { vesting: VestingSchedule },
+    /// Stop the given vesting schedule and return the unvested tokens to the treasury.
+    RevokeVesting { vesting_id: u64 },
 }
 
 pub type InstructionKind = u8;
@@ -132,17 +246,176 @@ impl Instruction {
             Instruction::AddBounty { .. } => 9,
             Instruction::BountyDone { .. } => 10,
             Instruction::Vote => 11,
+            Instruction::CreateRecurringPayment { .. } => 12,
+            Instruction::CancelRecurringPayment { .. } => 13,
+            Instruction::CreateVesting { .. } => 14,
+            Instruction::RevokeVesting { .. } => 15,
+        }
+    }
+
+    /// Returns the amount that can be chosen by voters, if this instruction has one.
+    pub fn amount_mut(&mut self) -> Option<&mut U128> {
+        match self {
+            Instruction::Transfer { amount, .. } => Some(amount),
+            Instruction::AddBounty { bounty } => Some(&mut bounty.amount),
+            Instruction::CreateRecurringPayment { payment } => Some(&mut payment.amount),
+            Instruction::CreateVesting { vesting } => Some(&mut vesting.total),
+            _ => None,
+        }
+    }
+}
+
+/// Marks the amount of one of the instructions of a proposal version as chosen by
+/// the voters. Each voter approving the version submits an amount within the bounds
+/// and the instruction is executed with the weighted median of those amounts.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, PartialEq)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+#[serde(crate = "near_sdk::serde")]
+pub struct VotedAmount {
+    /// Index of the `Transfer` or `AddBounty` instruction whose amount is voted on.
+    pub instruction: u8,
+    /// Lowest amount voters can choose.
+    pub min: U128,
+    /// Highest amount voters can choose.
+    pub max: U128,
+}
+
+impl VotedAmount {
+    fn assert_valid(&self, instructions: &[Instruction]) {
+        let instr = instructions
+            .get(self.instruction as usize)
+            .expect("ERR_NO_INSTRUCTION");
+        assert!(
+            matches!(
+                instr,
+                Instruction::Transfer { .. }
+                    | Instruction::AddBounty { .. }
+                    | Instruction::CreateRecurringPayment { .. }
+                    | Instruction::CreateVesting { .. }
+            ),
+            "ERR_INSTRUCTION_HAS_NO_AMOUNT"
+        );
+        assert!(self.min.0 <= self.max.0, "ERR_INVALID_AMOUNT_BOUNDS");
+    }
+}
+
+/// Condition on another proposal that must hold before a proposal is executed.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Prerequisite {
+    /// The proposal with the given id must have been executed.
+    Executed(u64),
+    /// The proposal with the given id must not have been rejected, cancelled,
+    /// removed or have expired.
+    NotRejected(u64),
+}
+
+impl Prerequisite {
+    pub fn proposal_id(&self) -> u64 {
+        match self {
+            Prerequisite::Executed(id) | Prerequisite::NotRejected(id) => *id,
         }
     }
 }
 
+/// Optional settings of a new proposal.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Default)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProposalOptions {
+    /// Amount of one of the instructions to be chosen by the voters.
+    #[serde(default)]
+    pub voted_amount: Option<VotedAmount>,
+    /// Time before which the proposal is not executed once approved.
+    #[serde(default)]
+    pub execute_after: Option<WrappedTimestamp>,
+    /// Conditions on other proposals that must hold before the proposal is executed.
+    #[serde(default)]
+    pub prerequisites: Vec<Prerequisite>,
+    /// Apply the local instructions only once all promises of the proposal succeeded.
+    /// Promises that succeeded are not undone if another one fails.
+    #[serde(default)]
+    pub atomic: bool,
+}
+
+/// Choice of a vote on a proposal.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum VoteChoice {
+    /// Rejects the proposal.
+    Reject,
+    /// Approves the given version.
+    Approve(u8),
+    /// Counts toward the turnout but neither approves nor rejects the proposal.
+    Abstain,
+}
+
 /// Votes recorded in the proposal. Votes can be for any proposal within a
-/// proposal topic. A vote of 0
+/// proposal topic.
 #[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
 #[serde(crate = "near_sdk::serde")]
 pub struct Vote {
-    pub choice: u8,
+    pub choice: VoteChoice,
     pub weight: u128,
+    /// Versions in order of preference for ranked ballots, empty otherwise.
+    /// The first preference is also recorded as the choice.
+    pub ranking: Vec<u8>,
+    /// Versions approved by an approval ballot, empty otherwise.
+    pub approvals: Vec<u8>,
+    /// Amounts chosen by the voter for the backed versions with a voted amount.
+    pub amounts: HashMap<u8, U128>,
+    /// Weight of the members who delegated their vote to the voter, included in `weight`.
+    pub delegated: Vec<DelegatedWeight>,
+    /// Houses of bicameral votes the voter is a member of. Only the voter's own weight
+    /// is counted in them, delegated weight counts in the houses of its delegators.
+    pub houses: Vec<String>,
+}
+
+/// Votes of the members of a house of bicameral votes.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RoleTally {
+    /// Count of approvals per proposal version.
+    pub approve_count: Vec<Balance>,
+    /// Count of rejections over the entire proposal.
+    pub reject_count: Balance,
+}
+
+/// Weight a member lent to the vote of their delegate.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DelegatedWeight {
+    pub account_id: AccountId,
+    pub weight: Balance,
+    /// Houses of bicameral votes the delegator is a member of.
+    pub houses: Vec<String>,
+}
+
+impl Vote {
+    /// Returns the versions whose approve count is credited with this vote.
+    pub fn approved_versions(&self) -> Vec<u8> {
+        match self.choice {
+            VoteChoice::Approve(_) if !self.approvals.is_empty() => self.approvals.clone(),
+            VoteChoice::Approve(version) => vec![version],
+            VoteChoice::Reject | VoteChoice::Abstain => vec![],
+        }
+    }
+
+    /// Returns the weight this vote carries in each house of bicameral votes: the
+    /// voter's own weight in their houses and each delegator's weight in theirs.
+    pub fn house_weights(&self) -> HashMap<String, Balance> {
+        let delegated: Balance = self.delegated.iter().map(|d| d.weight).sum();
+        let mut weights = HashMap::new();
+        for house in self.houses.iter() {
+            *weights.entry(house.clone()).or_insert(0) += self.weight - delegated;
+        }
+        for delegator in self.delegated.iter() {
+            for house in delegator.houses.iter() {
+                *weights.entry(house.clone()).or_insert(0) += delegator.weight;
+            }
+        }
+        weights
+    }
 }
 
 #[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -167,19 +440,47 @@ pub struct Proposal {
     pub approve_count: Vec<Balance>,
     /// Count of rejections over the entire proposal.
     pub reject_count: Balance,
+    /// Count of abstentions over the entire proposal.
+    pub abstain_count: Balance,
+    /// Counts of the votes per house of bicameral votes.
+    pub role_tallies: HashMap<String, RoleTally>,
     /// Count of votes to remove a proposal version
     pub remove_count: Vec<Balance>,
     /// Flag to indicate the removal of a proposal
     pub remove_flag: Vec<bool>,
     /// Map of who voted to prevent multiple voting
     pub votes: HashMap<AccountId, Vote>,
+    /// Delegates whose votes carry the weight of the given delegators.
+    pub delegated_votes: HashMap<AccountId, AccountId>,
+    /// Weighted median of the amounts chosen for the approved version, if it has a
+    /// voted amount. Taken once the proposal is approved.
+    pub decided_amount: Option<U128>,
     /// Hashset of remove votes to prevent multiple voting
     pub remove_votes: Vec<RemoveVote>,
     /// Submission time (for voting period).
     pub submission_time: WrappedTimestamp,
+    /// Time voting opens, after the discussion period of the proposal kind.
+    pub voting_start: WrappedTimestamp,
+    /// Delegated tokens staked behind versions under conviction voting.
+    pub conviction_stakes: HashMap<AccountId, ConvictionStake>,
+    /// Conviction of each version under conviction voting.
+    pub convictions: Vec<Conviction>,
+    /// Hashes of the votes committed to under secret ballots that are not revealed yet.
+    pub commitments: HashMap<AccountId, Base58CryptoHash>,
+    /// Conditions on other proposals that must hold before this one is executed.
+    pub prerequisites: Vec<Prerequisite>,
+    /// Instructions of the approved version as they were run, with their outcomes.
+    pub execution: Vec<ExecutedInstruction>,
+    /// Hash of the policy at the time the local instructions of an atomic proposal
+    /// were staged. A staged policy change fails if the policy changed since.
+    pub staged_policy: Option<Base58CryptoHash>,
+    /// Proposal of another DAO this one was moved from.
+    pub moved_from: Option<ProposalLink>,
+    /// Proposal of the hub this one was moved to.
+    pub moved_to: Option<ProposalLink>,
 }
 
-#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
 #[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
 #[serde(crate = "near_sdk::serde")]
 pub struct ProposalVersion {
@@ -189,66 +490,436 @@ pub struct ProposalVersion {
     pub description: String,
     /// Instructions to be executed if proposal is approved.
     pub instructions: Vec<Instruction>,
+    /// Amount of one of the instructions that is chosen by the voters.
+    #[serde(default)]
+    pub voted_amount: Option<VotedAmount>,
+    /// Time before which this version is not executed once approved.
+    #[serde(default)]
+    pub execute_after: Option<WrappedTimestamp>,
+    /// Bond paid by the proposer, returned once the proposal is decided.
+    pub bond: U128,
+    /// Local instructions are staged until all promises succeeded, and reverted if
+    /// any failed. Bonds are returned once the execution settles. Only the local state
+    /// is atomic: promises that succeeded are not undone when another one fails.
+    #[serde(default)]
+    pub atomic: bool,
+}
+
+/// Status of a proposal as stored before execution was tracked.
+#[derive(BorshSerialize, BorshDeserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub enum ProposalStatusV1 {
+    InProgress,
+    Approved{ version: u8 },
+    Rejected,
+    Expired,
+    Moved,
+}
+
+/// Vote as stored before ballot kinds were introduced. A choice of 0 rejects the
+/// proposal, any other choice approves the version `choice - 1`.
+#[derive(BorshSerialize, BorshDeserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub struct VoteV1 {
+    pub choice: u8,
+    pub weight: u128,
+}
+
+/// Proposal version as stored before bonds were recorded per version.
+#[derive(BorshSerialize, BorshDeserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub struct ProposalVersionV1 {
+    pub proposer: AccountId,
+    pub description: String,
+    pub instructions: Vec<Instruction>,
+}
+
+/// Proposal as stored before ballot kinds were introduced.
+#[derive(BorshSerialize, BorshDeserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub struct ProposalV1 {
+    pub kind: String,
+    pub versions: Vec<ProposalVersionV1>,
+    pub status: ProposalStatusV1,
+    pub approve_count: Vec<Balance>,
+    pub reject_count: Balance,
+    pub remove_count: Vec<Balance>,
+    pub remove_flag: Vec<bool>,
+    pub votes: HashMap<AccountId, VoteV1>,
+    pub remove_votes: Vec<RemoveVote>,
+    pub submission_time: WrappedTimestamp,
 }
 
+/// Versioned proposal. Proposals stored before ballot kinds were introduced are
+/// read as `V1` and have to be upgraded by `migrate` before they can be used.
 #[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
 #[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
 #[serde(crate = "near_sdk::serde")]
+#[allow(clippy::large_enum_variant)]
 pub enum VersionedProposal {
+    #[serde(skip)]
+    V1(ProposalV1),
     Default(Proposal),
 }
 
+impl VersionedProposal {
+    /// Upgrades the proposal into the latest version. Versions of `V1` proposals are
+    /// recorded with the given bond, which is what their proposers paid.
+    pub fn upgrade(self, bond: U128) -> Self {
+        match self {
+            VersionedProposal::V1(p) => {
+                let count = p.versions.len();
+                VersionedProposal::Default(Proposal {
+                    kind: p.kind,
+                    versions: p
+                        .versions
+                        .into_iter()
+                        .map(|v| ProposalVersion {
+                            proposer: v.proposer,
+                            description: v.description,
+                            instructions: v.instructions,
+                            voted_amount: None,
+                            execute_after: None,
+                            bond,
+                            atomic: false,
+                        })
+                        .collect(),
+                    // approved proposals were executed right away
+                    status: match p.status {
+                        ProposalStatusV1::InProgress => ProposalStatus::InProgress,
+                        ProposalStatusV1::Approved { version } => ProposalStatus::Executed { version },
+                        ProposalStatusV1::Rejected => ProposalStatus::Rejected,
+                        ProposalStatusV1::Expired => ProposalStatus::Expired,
+                        ProposalStatusV1::Moved => ProposalStatus::Moved,
+                    },
+                    approve_count: p.approve_count,
+                    reject_count: p.reject_count,
+                    abstain_count: 0,
+                    role_tallies: HashMap::new(),
+                    remove_count: p.remove_count,
+                    remove_flag: p.remove_flag,
+                    votes: p
+                        .votes
+                        .into_iter()
+                        .map(|(account_id, vote)| {
+                            let choice = match vote.choice {
+                                0 => VoteChoice::Reject,
+                                choice => VoteChoice::Approve(choice - 1),
+                            };
+                            (account_id, Vote {
+                                choice,
+                                weight: vote.weight,
+                                ranking: vec![],
+                                approvals: vec![],
+                                amounts: HashMap::new(),
+                                delegated: vec![],
+                                houses: vec![],
+                            })
+                        })
+                        .collect(),
+                    delegated_votes: HashMap::new(),
+                    decided_amount: None,
+                    remove_votes: p.remove_votes,
+                    submission_time: p.submission_time,
+                    voting_start: p.submission_time,
+                    conviction_stakes: HashMap::new(),
+                    convictions: vec![Conviction::new(p.submission_time.0); count],
+                    commitments: HashMap::new(),
+                    prerequisites: vec![],
+                    execution: vec![],
+                    staged_policy: None,
+                    moved_from: None,
+                    moved_to: None,
+                })
+            }
+            VersionedProposal::Default(p) => VersionedProposal::Default(p),
+        }
+    }
+}
+
 impl From<VersionedProposal> for Proposal {
     fn from(v: VersionedProposal) -> Self {
         match v {
             VersionedProposal::Default(p) => p,
+            VersionedProposal::V1(_) => env::panic(b"ERR_PROPOSAL_NOT_MIGRATED"),
         }
     }
 }
 
 impl Proposal {
-    /// Adds vote of the given user with given `amount` of weight. If user already voted, fails.
+    /// Adds vote of the given user with given `amount` of weight. If the user already
+    /// voted, their previous vote is replaced. `total_weight` is the weight the
+    /// threshold of the vote policy is computed over and `house_thresholds` are the
+    /// thresholds of the houses of bicameral votes.
     pub fn update_votes(
         &mut self,
         account_id: &AccountId,
-        vote: Vote,
-        threshold: u128
+        mut vote: Vote,
+        vote_policy: &VotePolicy,
+        total_weight: u128,
+        house_thresholds: &[(String, Balance)],
     ) -> ProposalStatus {
-        // add vote to tally and check previous votes
-        let old_vote = self.votes.insert(account_id.clone(), vote.clone());
+        if let VoteChoice::Approve(version) = vote.choice {
+            assert!(!self.remove_flag[version as usize], "ERR_PROPOSAL_REMOVED");
+        }
+
         // if the voter previously voted then revert it
-        let mut weight = vote.weight;
-        if old_vote.is_some() {
-            let v = old_vote.unwrap();
-            if v.choice == 0 {
-                self.reject_count -= v.weight
-            } else {
-                self.approve_count[(v.choice - 1) as usize] += v.weight
-            }
+        if let Some(old_vote) = self.votes.get(account_id).cloned() {
+            self.remove_from_tally(&old_vote);
             // set the weight to what it was when the user first voted
-            weight = v.weight
+            vote.weight = old_vote.weight;
+            vote.delegated = old_vote.delegated;
+            vote.houses = old_vote.houses;
         }
+        for delegated in vote.delegated.iter() {
+            self.delegated_votes
+                .insert(delegated.account_id.clone(), account_id.clone());
+        }
+        self.add_to_tally(&vote);
+        self.votes.insert(account_id.clone(), vote);
 
-        assert!(!self.remove_flag[(vote.choice - 1) as usize], "ERR_PROPOSAL_REMOVED");
-
-        // version should have already been vetted
-        if vote.choice == 0 {
-            self.reject_count -= weight;
-        } else {
-            self.approve_count[(vote.choice - 1) as usize] += weight;
+        if !house_thresholds.is_empty() {
+            return self
+                .house_status(house_thresholds)
+                .unwrap_or(ProposalStatus::InProgress)
         }
 
+        let threshold = match vote_policy.decision_threshold(total_weight, self.turnout(), false) {
+            Some(threshold) => threshold,
+            None => return ProposalStatus::InProgress,
+        };
         if self.reject_count >= threshold {
             return ProposalStatus::Rejected
         }
-        
-        if self.approve_count[(vote.choice - 1) as usize] >= threshold {
-            return ProposalStatus::Approved{ version: (vote.choice - 1) }
+
+        // ranked ballots are only decided once the proposal period is over
+        if vote_policy.ballot_kind != BallotKind::Ranked {
+            if let Some(version) = self.leading_version(threshold) {
+                return ProposalStatus::Approved{ version }
+            }
         }
 
         ProposalStatus::InProgress
     }
 
+    /// Takes the weight of the given member out of the vote of their delegate, so
+    /// that the member can vote directly.
+    pub fn remove_delegated_weight(&mut self, account_id: &AccountId) {
+        if let Some(delegate_id) = self.delegated_votes.remove(account_id) {
+            let mut vote = self.votes.remove(&delegate_id).unwrap();
+            self.remove_from_tally(&vote);
+            let index = vote
+                .delegated
+                .iter()
+                .position(|d| &d.account_id == account_id)
+                .unwrap();
+            vote.weight -= vote.delegated.remove(index).weight;
+            self.add_to_tally(&vote);
+            self.votes.insert(delegate_id, vote);
+        }
+    }
+
+    /// Returns true if the vote of the given member is already counted, either
+    /// directly or through their delegate, or if they committed to a secret vote.
+    pub fn has_voted(&self, account_id: &AccountId) -> bool {
+        self.votes.contains_key(account_id)
+            || self.commitments.contains_key(account_id)
+            || self.delegated_votes.contains_key(account_id)
+    }
+
+    /// Returns true if the versions can no longer be countered or amended, which is
+    /// the case once the discussion period of the proposal is over.
+    pub fn versions_frozen(&self) -> bool {
+        self.voting_start.0 > self.submission_time.0 && env::block_timestamp() >= self.voting_start.0
+    }
+
+    /// Returns the total weight of all votes cast.
+    pub fn turnout(&self) -> Balance {
+        self.votes.values().map(|v| v.weight).sum()
+    }
+
+    fn add_to_tally(&mut self, vote: &Vote) {
+        match vote.choice {
+            VoteChoice::Reject => self.reject_count += vote.weight,
+            VoteChoice::Abstain => self.abstain_count += vote.weight,
+            VoteChoice::Approve(_) => {}
+        }
+        for version in vote.approved_versions() {
+            self.approve_count[version as usize] += vote.weight;
+        }
+        let versions = self.versions.len();
+        for (house, weight) in vote.house_weights() {
+            let tally = self.role_tallies.entry(house).or_insert(RoleTally {
+                approve_count: vec![0; versions],
+                reject_count: 0,
+            });
+            if vote.choice == VoteChoice::Reject {
+                tally.reject_count += weight;
+            }
+            for version in vote.approved_versions() {
+                tally.approve_count[version as usize] += weight;
+            }
+        }
+    }
+
+    fn remove_from_tally(&mut self, vote: &Vote) {
+        match vote.choice {
+            VoteChoice::Reject => self.reject_count -= vote.weight,
+            VoteChoice::Abstain => self.abstain_count -= vote.weight,
+            VoteChoice::Approve(_) => {}
+        }
+        for version in vote.approved_versions() {
+            self.approve_count[version as usize] -= vote.weight;
+        }
+        for (house, weight) in vote.house_weights() {
+            let tally = self.role_tallies.get_mut(&house).unwrap();
+            if vote.choice == VoteChoice::Reject {
+                tally.reject_count -= weight;
+            }
+            for version in vote.approved_versions() {
+                tally.approve_count[version as usize] -= weight;
+            }
+        }
+    }
+
+    /// Returns the decision of the houses of bicameral votes given the threshold of
+    /// each house. The earliest version approved by every house passes.
+    pub fn house_status(&self, house_thresholds: &[(String, Balance)]) -> Option<ProposalStatus> {
+        let tally = |house: &String| self.role_tallies.get(house);
+        for (house, threshold) in house_thresholds {
+            if tally(house).map(|t| t.reject_count).unwrap_or_default() >= *threshold {
+                return Some(ProposalStatus::Rejected)
+            }
+        }
+        (0..self.versions.len())
+            .filter(|v| !self.remove_flag[*v])
+            .find(|v| {
+                house_thresholds.iter().all(|(house, threshold)| {
+                    tally(house).map(|t| t.approve_count[*v]).unwrap_or_default() >= *threshold
+                })
+            })
+            .map(|v| ProposalStatus::Approved{ version: v as u8 })
+    }
+
+    /// Returns the version with the most approvals if it reached the threshold.
+    /// Several versions can pass at once with approval ballots, the earliest one
+    /// wins a tie.
+    pub fn leading_version(&self, threshold: Balance) -> Option<u8> {
+        let leader = (0..self.approve_count.len())
+            .rev()
+            .max_by_key(|v| self.approve_count[*v])?;
+        if self.approve_count[leader] >= threshold {
+            Some(leader as u8)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the version with the most approvals that hasn't been removed, the
+    /// earliest one on a tie.
+    pub fn preferred_version(&self) -> Option<u8> {
+        (0..self.versions.len())
+            .filter(|v| !self.remove_flag[*v])
+            .rev()
+            .max_by_key(|v| self.approve_count[*v])
+            .map(|v| v as u8)
+    }
+
+    /// Runs an instant-runoff tally over the ranked ballots. Each round the standing
+    /// version with the least first preferences is eliminated (the most recent one
+    /// on a tie) and its ballots move to their next standing preference.
+    /// Returns the first version to reach the threshold, if any.
+    pub fn instant_runoff(&self, threshold: Balance) -> Option<u8> {
+        let mut standing: Vec<bool> = self.remove_flag.iter().map(|removed| !removed).collect();
+        loop {
+            let mut tally: Vec<Balance> = vec![0; self.versions.len()];
+            for vote in self.votes.values() {
+                if let Some(version) = vote.ranking.iter().find(|v| standing[**v as usize]) {
+                    tally[*version as usize] += vote.weight;
+                }
+            }
+            let candidates: Vec<usize> = (0..self.versions.len()).filter(|v| standing[*v]).collect();
+            // the highest tally wins, the earliest version on a tie
+            let leader = *candidates.iter().rev().max_by_key(|v| tally[**v])?;
+            if tally[leader] >= threshold {
+                return Some(leader as u8)
+            }
+            let last = *candidates.iter().min_by_key(|v| tally[**v]).unwrap();
+            let last = *candidates.iter().rev().find(|v| tally[**v] == tally[last]).unwrap();
+            standing[last] = false;
+        }
+    }
+
+    /// Returns the weighted median of the amounts chosen for the given version by
+    /// the votes backing it.
+    pub fn median_amount(&self, version: u8) -> Option<Balance> {
+        let mut amounts: Vec<(Balance, Balance)> = self
+            .votes
+            .values()
+            .filter_map(|v| v.amounts.get(&version).map(|amount| (amount.0, v.weight)))
+            .collect();
+        amounts.sort_unstable();
+        let total: Balance = amounts.iter().map(|(_, weight)| weight).sum();
+        let mut cumulative = 0;
+        for (amount, weight) in amounts {
+            cumulative += weight;
+            if cumulative * 2 >= total {
+                return Some(amount)
+            }
+        }
+        None
+    }
+
+    /// Returns the given version as it should be executed, with the decided amount
+    /// filled in. Without any chosen amounts, the proposed amount is clamped to the bounds.
+    pub fn version_to_execute(&self, version: u8) -> ProposalVersion {
+        let mut v = self.versions[version as usize].clone();
+        if let Some(voted) = v.voted_amount.clone() {
+            let amount = v.instructions[voted.instruction as usize]
+                .amount_mut()
+                .expect("ERR_INSTRUCTION_HAS_NO_AMOUNT");
+            let chosen = self.decided_amount.map(|a| a.0).unwrap_or(amount.0);
+            *amount = U128(chosen.max(voted.min.0).min(voted.max.0));
+        }
+        v
+    }
+
+    /// Returns the versions that were not removed.
+    pub fn standing_versions(&self) -> Vec<ProposalVersion> {
+        self.versions
+            .iter()
+            .zip(self.remove_flag.iter())
+            .filter(|(_, removed)| !**removed)
+            .map(|(version, _)| version.clone())
+            .collect()
+    }
+
+    /// Marks an approved proposal as executed, or as failed, once none of the
+    /// instructions it ran are pending anymore.
+    pub fn update_execution_status(&mut self) {
+        let version = match self.status {
+            ProposalStatus::Approved { version } => version,
+            _ => return,
+        };
+        let outcomes = self.execution.iter().map(|e| &e.outcome);
+        if outcomes.clone().any(|o| *o == InstructionOutcome::Pending) {
+            return;
+        }
+        self.status = if outcomes.clone().any(|o| *o == InstructionOutcome::Failed) {
+            ProposalStatus::Failed { version }
+        } else {
+            ProposalStatus::Executed { version }
+        };
+    }
+
+    /// Returns true if anyone has voted on the given version. Secret votes only count
+    /// once revealed, and revealing a vote for a withdrawn version fails.
+    pub fn has_votes(&self, version: u8) -> bool {
+        self.approve_count[version as usize] > 0
+            || self.remove_count[version as usize] > 0
+            || self.votes.values().any(|v| v.ranking.contains(&version))
+    }
+
     pub fn update_remove_votes(
         &mut self, 
         vote: RemoveVote, 
@@ -268,209 +939,889 @@ impl Proposal {
         false
     }
 
+    /// Creates the vote of a user. `versions` are the versions ranked or approved by
+    /// the ballot and must be empty for single choice ballots. `amounts` holds the
+    /// amount chosen for each backed version with a voted amount.
     pub fn create_vote(&self, 
         vote_policy: &VotePolicy, 
-        choice: u8, 
+        choice: VoteChoice, 
+        mut versions: Vec<u8>,
+        amounts: HashMap<u8, U128>,
         user_weight: Balance
     ) -> Vote {
-        assert!(choice <= self.versions.len() as u8, "ERR_NO_PROPOSAL_VERSION");
+        let approved = match choice {
+            VoteChoice::Approve(version) => {
+                assert!((version as usize) < self.versions.len(), "ERR_NO_PROPOSAL_VERSION");
+                Some(version)
+            }
+            VoteChoice::Reject | VoteChoice::Abstain => None,
+        };
+        if vote_policy.ballot_kind == BallotKind::Single || choice == VoteChoice::Abstain {
+            assert!(versions.is_empty(), "ERR_WRONG_BALLOT_KIND");
+        }
+        // a vote for a single version is a ballot with only that version
+        if let (true, Some(version)) = (versions.is_empty(), approved) {
+            if vote_policy.ballot_kind != BallotKind::Single {
+                versions.push(version);
+            }
+        }
+        for (i, version) in versions.iter().enumerate() {
+            assert!(*version < self.versions.len() as u8, "ERR_NO_PROPOSAL_VERSION");
+            assert!(!self.remove_flag[*version as usize], "ERR_PROPOSAL_REMOVED");
+            assert!(!versions[..i].contains(version), "ERR_DUPLICATE_VERSION");
+        }
+        let backed = match approved {
+            Some(version) if versions.is_empty() => vec![version],
+            _ => versions.clone(),
+        };
+        for version in backed.iter() {
+            if let Some(voted) = &self.versions[*version as usize].voted_amount {
+                let amount = amounts.get(version).expect("ERR_AMOUNT_REQUIRED").0;
+                assert!(voted.min.0 <= amount && amount <= voted.max.0, "ERR_AMOUNT_OUT_OF_BOUNDS");
+            }
+        }
+        assert!(
+            amounts.keys().all(|version| {
+                backed.contains(version) && self.versions[*version as usize].voted_amount.is_some()
+            }),
+            "ERR_UNEXPECTED_AMOUNT"
+        );
         // calculate the weight of the vote
-        let weight = match vote_policy.weight_kind {
-            WeightKind::TokenWeight => user_weight,
-            WeightKind::RoleWeight => 1,
+        let weight = vote_policy.vote_weight(user_weight);
+        let (ranking, approvals) = match vote_policy.ballot_kind {
+            BallotKind::Ranked => (versions, vec![]),
+            BallotKind::Approval => (vec![], versions),
+            BallotKind::Single => (vec![], vec![]),
         };
         Vote {
             choice,
             weight,
+            ranking,
+            approvals,
+            amounts,
+            delegated: vec![],
+            houses: vec![],
         }
     }
 }
 
+#[ext_contract(ext_self)]
+pub trait ExtSelf {
+    fn on_instruction_executed(&mut self, id: u64, index: u64);
+    fn on_proposal_moved(&mut self, id: u64, hub_id: AccountId);
+    fn on_payment_claimed(&mut self, id: u64, count: u32);
+    fn on_vested_claimed(&mut self, id: u64, amount: U128);
+    fn on_payout_balance(&mut self, token_id: AccountId, receiver_id: AccountId, amount: U128) -> Promise;
+    fn on_reserve_balance(&mut self, id: u64, index: u64);
+}
+
+/// Transfers the given amount of a fungible token from this DAO.
+pub(crate) fn ft_transfer(token_id: &AccountId, receiver_id: &AccountId, amount: Balance) -> Promise {
+    ext_fungible_token::ft_transfer(
+        receiver_id.clone(),
+        U128(amount),
+        None,
+        token_id,
+        ONE_YOCTO_NEAR,
+        GAS_FOR_FT_TRANSFER,
+    )
+}
+
+/// Another DAO proposals are moved to.
+#[ext_contract(ext_hub)]
+pub trait Hub {
+    fn accept_moved_proposal(&mut self, source_id: u64, versions: Vec<ProposalVersion>) -> u64;
+}
+
 impl Contract {
-    /// Execute payout of given token to given user.
+    /// Execute payout of given token to given user, without touching reserved funds.
+    /// Returns None if not enough $NEAR is available. Other tokens that have funds
+    /// reserved are only paid out once the balance of this contract is checked.
     pub(crate) fn internal_payout(
         &mut self,
         token_id: &AccountId,
         receiver_id: &AccountId,
         amount: Balance,
-    ) -> PromiseOrValue<()> {
+    ) -> Option<Promise> {
         if token_id == BASE_TOKEN {
-            Promise::new(receiver_id.clone()).transfer(amount).into()
+            if !self.internal_can_spend(amount) {
+                return None;
+            }
+            Some(Promise::new(receiver_id.clone()).transfer(amount))
+        } else if self.internal_reserved_amount(token_id) == 0 {
+            Some(ft_transfer(token_id, receiver_id, amount))
         } else {
-            ext_fungible_token::ft_transfer(
-                receiver_id.clone(),
-                U128(amount),
-                None,
-                &token_id,
-                ONE_YOCTO_NEAR,
-                GAS_FOR_FT_TRANSFER,
+            Some(
+                ext_fungible_token::ft_balance_of(
+                    env::current_account_id(),
+                    token_id,
+                    0,
+                    GAS_FOR_FT_BALANCE_OF,
+                )
+                .then(ext_self::on_payout_balance(
+                    token_id.clone(),
+                    receiver_id.clone(),
+                    U128(amount),
+                    &env::current_account_id(),
+                    0,
+                    GAS_FOR_PAYOUT_CALLBACK,
+                )),
             )
-            .into()
         }
     }
 
-    /// Executes given proposal and updates the contract's state.
-    fn internal_execute_proposal(
+    /// Executes the approved version of the given proposal, or queues it if the
+    /// proposal kind has an execution delay or the version is scheduled for later.
+    pub(crate) fn internal_approve_proposal(
         &mut self,
+        id: u64,
         policy: &Policy,
-        proposal: &Proposal,
-        version: &ProposalVersion,
+        proposal: &mut Proposal,
+        version: u8,
     ) {
-        // Return the proposal bond to all proposers.
-        for p in proposal.versions.iter() {
-            Promise::new(p.proposer.clone()).transfer(policy.proposal_bond.0);
-        }
-        // execute instructions in order of proposal
-        for instr in &version.instructions {
-            match instr {
-                Instruction::ChangeConfig { config } => {
-                    self.config.set(config);
-                }
-                Instruction::ChangePolicy { policy } => {
-                    self.policy.set(policy);
-                }
-                Instruction::AddMemberToRole { member_id, role } => {
-                    let mut new_policy = policy.clone();
-                    new_policy.add_member_to_role(role, member_id);
-                    self.policy.set(&VersionedPolicy::Current(new_policy));
-                }
-                Instruction::RemoveMemberFromRole { member_id, role } => {
-                    let mut new_policy = policy.clone();
-                    new_policy.remove_member_from_role(role, member_id);
-                    self.policy.set(&VersionedPolicy::Current(new_policy));
-                }
-                Instruction::FunctionCall {
-                    receiver_id,
-                    actions,
-                } => {
-                    let mut promise = Promise::new(receiver_id.clone());
-                    for action in actions {
-                        promise = promise.function_call(
-                            action.method_name.clone().into_bytes(),
-                            action.args.clone().into(),
-                            action.deposit.0,
-                            action.gas.0,
-                        )
-                    }
-                }
-                Instruction::UpgradeSelf { hash } => {
-                    upgrade_self(&CryptoHash::from(hash.clone()));
-                }
-                Instruction::UpgradeRemote {
-                    receiver_id,
-                    method_name,
-                    hash,
-                } => {
-                    upgrade_remote(receiver_id, method_name, &CryptoHash::from(hash.clone()));
-                }
-                Instruction::Transfer {
-                    token_id,
-                    receiver_id,
-                    amount,
-                } => {
-                    self.internal_payout(token_id, receiver_id, amount.0);
-                },
-                Instruction::SetStakingContract { staking_id } => {
-                    assert!(self.staking_id.is_none(), "ERR_INVALID_STAKING_CHANGE");
-                    self.staking_id = Some(staking_id.clone());
-                }
-                Instruction::AddBounty { bounty } => {
-                    self.internal_add_bounty(bounty);
-                }
-                Instruction::BountyDone {
-                    bounty_id,
-                    receiver_id,
-                } => {
-                    self.internal_execute_bounty_payout(*bounty_id, receiver_id, true);
-                },
-                Instruction::Vote => {}
+        proposal.decided_amount = proposal.median_amount(version).map(U128);
+        let now = env::block_timestamp();
+        let delay = policy
+            .get_execution_delay(&proposal.kind)
+            .map(|delay| delay.0)
+            .unwrap_or_default();
+        let scheduled = proposal.versions[version as usize]
+            .execute_after
+            .map(|t| t.0)
+            .unwrap_or_default();
+        let execute_after = std::cmp::max(now + delay, scheduled);
+        let awaiting_prerequisites = !self.internal_prerequisites_met(proposal);
+        if self.internal_prerequisites_failed(proposal) {
+            self.internal_reject_proposal(proposal);
+            proposal.status = ProposalStatus::Rejected;
+        } else if execute_after > now || awaiting_prerequisites {
+            // proposals are held in the queue until their prerequisites are met
+            proposal.status = ProposalStatus::Queued {
+                version,
+                execute_after: WrappedTimestamp::from(execute_after),
+                awaiting_prerequisites,
+            };
+            self.queued_proposals.insert(&id);
+            for prerequisite in proposal.prerequisites.iter() {
+                let mut dependents = self
+                    .dependent_proposals
+                    .get(&prerequisite.proposal_id())
+                    .unwrap_or_default();
+                dependents.push(id);
+                self.dependent_proposals
+                    .insert(&prerequisite.proposal_id(), &dependents);
             }
+        } else {
+            self.internal_execute_proposal(id, policy, proposal, version);
         }
     }
 
-    /// Process rejecting proposal.
-    fn internal_reject_proposal(
-        &mut self,
-        policy: &Policy,
-        proposal: &Proposal
-    ) {
-        for p in proposal.versions.iter() {
-            // Return bond to all proposers.
-            Promise::new(p.proposer.clone()).transfer(policy.proposal_bond.0);
-            for instr in p.instructions.iter() {
-                match instr {
-                    Instruction::BountyDone {
-                        bounty_id,
-                        receiver_id,
-                    } => {
-                        self.internal_execute_bounty_payout(*bounty_id, receiver_id, false);
-                    },
-                    _ => {}
-                }
+    /// Returns true if a condition on the prerequisites of the given proposal can never hold.
+    fn internal_prerequisites_failed(&self, proposal: &Proposal) -> bool {
+        proposal.prerequisites.iter().any(|prerequisite| {
+            // removed proposals don't meet any condition
+            let status = match self.proposals.get(&prerequisite.proposal_id()) {
+                Some(p) => Proposal::from(p).status,
+                None => return true,
+            };
+            let rejected = matches!(
+                status,
+                ProposalStatus::Rejected | ProposalStatus::Cancelled | ProposalStatus::Expired
+            );
+            match prerequisite {
+                Prerequisite::Executed(_) => rejected || status == ProposalStatus::Moved,
+                Prerequisite::NotRejected(_) => rejected,
             }
-        }
+        })
     }
 
-    pub(crate) fn internal_user_info(&self) -> UserInfo {
-        let account_id = env::predecessor_account_id();
-        UserInfo {
-            amount: self.get_user_weight(&account_id),
-            account_id,
+    /// Settles the queued proposals that depend on the given proposal once it is
+    /// decided. Dependents whose prerequisites can't be met anymore are rejected and
+    /// their bonds returned, the others are released once all prerequisites are met.
+    pub(crate) fn internal_resolve_dependents(&mut self, id: u64) {
+        let decided = match self.proposals.get(&id) {
+            Some(p) => !matches!(
+                Proposal::from(p).status,
+                ProposalStatus::InProgress
+                    | ProposalStatus::Approved { .. }
+                    | ProposalStatus::Failed { .. }
+                    | ProposalStatus::Queued { .. }
+            ),
+            None => true,
+        };
+        if !decided {
+            return;
         }
-    }
-}
+        for dependent_id in self.dependent_proposals.remove(&id).unwrap_or_default() {
+            let mut dependent: Proposal = match self.proposals.get(&dependent_id) {
+                Some(p) => p.into(),
+                None => continue,
+            };
+            let (version, execute_after) = match dependent.status {
+                ProposalStatus::Queued { version, execute_after, .. } => (version, execute_after),
+                _ => continue,
+            };
+            if self.internal_prerequisites_failed(&dependent) {
+                self.queued_proposals.remove(&dependent_id);
+                self.internal_reject_proposal(&mut dependent);
+                dependent.status = ProposalStatus::Rejected;
+            } else {
+                dependent.status = ProposalStatus::Queued {
+                    version,
+                    execute_after,
+                    awaiting_prerequisites: !self.internal_prerequisites_met(&dependent),
+                };
+            }
+            let rejected = dependent.status == ProposalStatus::Rejected;
+            self.proposals
+                .insert(&dependent_id, &VersionedProposal::Default(dependent));
+            if rejected {
+                self.internal_resolve_dependents(dependent_id);
+            }
+        }
+    }
 
-#[near_bindgen]
-impl Contract {
-    /// Add proposal to this DAO.
-    #[payable]
-    pub fn propose(&mut self, description: String, instructions: Vec<Instruction>) -> u64 {
-        let kind = self.internal_check_proposal(&instructions);
+    /// Returns true if the conditions on the prerequisites of the given proposal hold.
+    pub(crate) fn internal_prerequisites_met(&self, proposal: &Proposal) -> bool {
+        proposal.prerequisites.iter().all(|prerequisite| {
+            // removed proposals don't meet any condition
+            let status = match self.proposals.get(&prerequisite.proposal_id()) {
+                Some(p) => Proposal::from(p).status,
+                None => return false,
+            };
+            match prerequisite {
+                Prerequisite::Executed(_) => matches!(status, ProposalStatus::Executed { .. }),
+                Prerequisite::NotRejected(_) => !matches!(
+                    status,
+                    ProposalStatus::Rejected | ProposalStatus::Cancelled | ProposalStatus::Expired
+                ),
+            }
+        })
+    }
 
-        let p = Proposal {
-            versions: vec![
-                ProposalVersion {
-                    proposer: env::predecessor_account_id(),
-                    instructions: instructions,
-                    description: description,
+    /// Executes the given version of the proposal and updates the contract's state.
+    pub(crate) fn internal_execute_proposal(
+        &mut self,
+        id: u64,
+        policy: &Policy,
+        proposal: &mut Proposal,
+        version: u8,
+    ) {
+        self.internal_release_conviction(proposal);
+        let atomic = proposal.versions[version as usize].atomic;
+        if !atomic {
+            self.internal_refund_bonds(proposal);
+        }
+        proposal.status = ProposalStatus::Approved { version };
+        if atomic {
+            proposal.staged_policy = Some(self.internal_policy_hash());
+        }
+        let instructions = proposal.version_to_execute(version).instructions;
+        let mut reserved_gas = self.internal_assert_gas(instructions.iter());
+        // execute instructions in order of proposal
+        proposal.execution = instructions
+            .into_iter()
+            .enumerate()
+            .map(|(index, instr)| {
+                reserved_gas -= self.internal_instruction_gas(&instr);
+                self.internal_execute_instruction(id, index as u64, policy, instr, atomic, reserved_gas)
+            })
+            .collect();
+        self.internal_update_execution(id, policy, proposal);
+    }
+
+    /// Returns the gas the promises of the given instruction and the callback recording
+    /// its outcome need.
+    fn internal_instruction_gas(&self, instr: &Instruction) -> Gas {
+        let payout_gas = |token_id: &AccountId| {
+            if token_id == BASE_TOKEN {
+                0
+            } else if self.internal_reserved_amount(token_id) == 0 {
+                GAS_FOR_FT_TRANSFER
+            } else {
+                GAS_FOR_FT_BALANCE_OF + GAS_FOR_PAYOUT_CALLBACK
+            }
+        };
+        let gas = match instr {
+            Instruction::FunctionCall { actions, .. } => actions.iter().map(|a| a.gas.0).sum(),
+            Instruction::UpgradeRemote { .. } => GAS_FOR_UPGRADE_REMOTE_DEPLOY,
+            Instruction::Transfer { token_id, .. } => payout_gas(token_id),
+            Instruction::CreateRecurringPayment { payment } if payment.token_id != BASE_TOKEN => {
+                GAS_FOR_FT_BALANCE_OF + GAS_FOR_RESERVE_CALLBACK
+            }
+            Instruction::CreateVesting { vesting } if vesting.token_id != BASE_TOKEN => {
+                GAS_FOR_FT_BALANCE_OF + GAS_FOR_RESERVE_CALLBACK
+            }
+            Instruction::BountyDone { bounty_id, .. } => match self.bounties.get(bounty_id) {
+                Some(bounty) => payout_gas(&Bounty::from(bounty).token),
+                None => 0,
+            },
+            _ => return 0,
+        };
+        gas + GAS_FOR_EXECUTION_CALLBACK
+    }
+
+    /// Checks that the prepaid gas covers the promises of the given instructions and
+    /// their callbacks, so that no outcome is left pending, and returns the gas needed.
+    fn internal_assert_gas<'a>(&self, instructions: impl Iterator<Item = &'a Instruction>) -> Gas {
+        let gas: Gas = instructions.map(|instr| self.internal_instruction_gas(instr)).sum();
+        assert!(env::prepaid_gas() - env::used_gas() >= gas, "ERR_NOT_ENOUGH_GAS");
+        gas
+    }
+
+    /// Updates the status of an approved proposal once none of its instructions are
+    /// pending. Staged instructions are applied if all promises succeeded and reverted
+    /// otherwise. The bonds still held are returned once the execution settles.
+    fn internal_update_execution(&mut self, id: u64, policy: &Policy, proposal: &mut Proposal) {
+        let outcomes: Vec<InstructionOutcome> =
+            proposal.execution.iter().map(|e| e.outcome.clone()).collect();
+        if outcomes.contains(&InstructionOutcome::Pending) {
+            return;
+        }
+        let failed = outcomes.contains(&InstructionOutcome::Failed);
+        let staged: Vec<usize> = (0..outcomes.len())
+            .filter(|index| outcomes[*index] == InstructionOutcome::Staged)
+            .collect();
+        // staged instructions are checked again against the state they are applied to,
+        // so that they are applied all together or not at all.
+        let policy_changed = proposal.staged_policy != Some(self.internal_policy_hash());
+        let invalid: Vec<usize> = staged
+            .iter()
+            .copied()
+            .filter(|index| {
+                let instr = &proposal.execution[*index].instruction;
+                !self.internal_can_apply(policy, instr)
+                    || (policy_changed && matches!(instr, Instruction::ChangePolicy { .. }))
+            })
+            .collect();
+        for index in staged {
+            if invalid.contains(&index) {
+                proposal.execution[index].outcome = InstructionOutcome::Failed;
+            } else if failed || !invalid.is_empty() {
+                proposal.execution[index].outcome = InstructionOutcome::Reverted;
+            } else {
+                let instr = proposal.execution[index].instruction.clone();
+                proposal.execution[index] =
+                    self.internal_execute_instruction(id, index as u64, policy, instr, false, 0);
+            }
+        }
+        proposal.update_execution_status();
+        self.internal_refund_bonds(proposal);
+    }
+
+    /// Returns the hash of the current policy.
+    fn internal_policy_hash(&self) -> Base58CryptoHash {
+        let mut hash = CryptoHash::default();
+        hash.copy_from_slice(&env::sha256(&self.policy.get().unwrap().try_to_vec().unwrap()));
+        hash.into()
+    }
+
+    /// Returns false if the given local instruction can't be applied to the current
+    /// state, in which case it fails instead of being applied.
+    fn internal_can_apply(&self, policy: &Policy, instr: &Instruction) -> bool {
+        match instr {
+            Instruction::AddMemberToRole { role, .. }
+            | Instruction::RemoveMemberFromRole { role, .. } => policy
+                .roles
+                .iter()
+                .any(|r| &r.name == role && matches!(r.kind, RoleKind::Group(_))),
+            Instruction::SetStakingContract { .. } => self.staking_id.is_none(),
+            Instruction::ChangePolicy { policy: new_policy } => {
+                self.internal_keeps_lock_duration(policy, new_policy)
+            }
+            Instruction::CancelRecurringPayment { payment_id } => {
+                self.payments.get(payment_id).is_some()
+            }
+            Instruction::RevokeVesting { vesting_id } => matches!(
+                self.vestings.get(vesting_id),
+                Some(vesting) if vesting.revoked_at.is_none()
+            ),
+            _ => true,
+        }
+    }
+
+    /// Executes a single instruction of the given proposal. Instructions that run
+    /// through promises are pending until `on_instruction_executed` records their outcome.
+    /// With `stage` set, local instructions are only staged to be applied later.
+    /// `reserved_gas` is kept for the instructions that run after this one.
+    fn internal_execute_instruction(
+        &mut self,
+        id: u64,
+        index: u64,
+        policy: &Policy,
+        instr: Instruction,
+        stage: bool,
+        reserved_gas: Gas,
+    ) -> ExecutedInstruction {
+        // bounty payouts settle the claim right away, as part of paying it out.
+        // payments and vesting in a fungible token wait for the balance of the token
+        // to be checked.
+        let remote = match &instr {
+            Instruction::FunctionCall { .. }
+            | Instruction::UpgradeRemote { .. }
+            | Instruction::Transfer { .. }
+            | Instruction::BountyDone { .. } => true,
+            Instruction::CreateRecurringPayment { payment } => payment.token_id != BASE_TOKEN,
+            Instruction::CreateVesting { vesting } => vesting.token_id != BASE_TOKEN,
+            _ => false,
+        };
+        if stage && !remote {
+            return ExecutedInstruction {
+                instruction: instr,
+                outcome: InstructionOutcome::Staged,
+            };
+        }
+        let callback = || {
+            ext_self::on_instruction_executed(
+                id,
+                index,
+                &env::current_account_id(),
+                0,
+                GAS_FOR_EXECUTION_CALLBACK,
+            )
+        };
+        let outcome = match &instr {
+            instr if !self.internal_can_apply(policy, instr) => InstructionOutcome::Failed,
+            Instruction::ChangeConfig { config } => {
+                self.config.set(config);
+                InstructionOutcome::Succeeded
+            }
+            Instruction::ChangePolicy { policy } => {
+                self.policy.set(policy);
+                InstructionOutcome::Succeeded
+            }
+            Instruction::AddMemberToRole { member_id, role } => {
+                let mut new_policy = policy.clone();
+                new_policy.add_member_to_role(role, member_id);
+                self.policy.set(&VersionedPolicy::Current(new_policy));
+                InstructionOutcome::Succeeded
+            }
+            Instruction::RemoveMemberFromRole { member_id, role } => {
+                let mut new_policy = policy.clone();
+                new_policy.remove_member_from_role(role, member_id);
+                self.policy.set(&VersionedPolicy::Current(new_policy));
+                InstructionOutcome::Succeeded
+            }
+            Instruction::FunctionCall {
+                receiver_id,
+                actions,
+            } if !self.internal_can_spend(actions.iter().map(|a| a.deposit.0).sum()) => {
+                InstructionOutcome::Failed
+            }
+            Instruction::FunctionCall {
+                receiver_id,
+                actions,
+            } => {
+                let mut promise = Promise::new(receiver_id.clone());
+                for action in actions {
+                    promise = promise.function_call(
+                        action.method_name.clone().into_bytes(),
+                        action.args.clone().into(),
+                        action.deposit.0,
+                        action.gas.0,
+                    )
                 }
-            ],
+                promise.then(callback());
+                InstructionOutcome::Pending
+            }
+            Instruction::UpgradeSelf { hash } => {
+                upgrade_self(&CryptoHash::from(hash.clone()));
+                InstructionOutcome::Succeeded
+            }
+            Instruction::UpgradeRemote {
+                receiver_id,
+                method_name,
+                hash,
+            } => {
+                // the upgrade gets all the gas the other instructions don't need
+                let gas = env::prepaid_gas()
+                    - env::used_gas()
+                    - reserved_gas
+                    - GAS_FOR_UPGRADE_REMOTE_DEPLOY
+                    - GAS_FOR_EXECUTION_CALLBACK;
+                upgrade_remote(receiver_id, method_name, &CryptoHash::from(hash.clone()), gas)
+                    .then(callback());
+                InstructionOutcome::Pending
+            }
+            Instruction::Transfer {
+                token_id,
+                receiver_id,
+                amount,
+            } => match self.internal_payout(token_id, receiver_id, amount.0) {
+                Some(promise) => {
+                    promise.then(callback());
+                    InstructionOutcome::Pending
+                }
+                // not enough $NEAR outside of reserved funds.
+                None => InstructionOutcome::Failed,
+            },
+            Instruction::SetStakingContract { staking_id } => {
+                self.staking_id = Some(staking_id.clone());
+                InstructionOutcome::Succeeded
+            }
+            Instruction::AddBounty { bounty } => {
+                self.internal_add_bounty(bounty);
+                InstructionOutcome::Succeeded
+            }
+            Instruction::BountyDone {
+                bounty_id,
+                receiver_id,
+            } => {
+                // the payout is kept as its transfer, so that retrying it doesn't
+                // depend on the bounty, which may be removed by now.
+                let transfer = self
+                    .internal_execute_bounty_payout(*bounty_id, receiver_id, true)
+                    .expect("ERR_NO_BOUNTY_PAYOUT");
+                return self.internal_execute_instruction(
+                    id,
+                    index,
+                    policy,
+                    transfer,
+                    stage,
+                    reserved_gas,
+                );
+            },
+            Instruction::Vote => InstructionOutcome::Succeeded,
+            Instruction::CreateRecurringPayment { payment } if payment.token_id != BASE_TOKEN => {
+                self.internal_check_reserve(id, index, &payment.token_id).then(callback());
+                InstructionOutcome::Pending
+            }
+            Instruction::CreateRecurringPayment { payment } => {
+                let total = payment.total(env::block_timestamp()).unwrap_or(Balance::MAX);
+                if !self.internal_can_spend(total) {
+                    InstructionOutcome::Failed
+                } else {
+                    self.internal_create_payment(payment);
+                    InstructionOutcome::Succeeded
+                }
+            }
+            Instruction::CancelRecurringPayment { payment_id } => {
+                self.internal_cancel_payment(*payment_id);
+                InstructionOutcome::Succeeded
+            }
+            Instruction::CreateVesting { vesting } if vesting.token_id != BASE_TOKEN => {
+                self.internal_check_reserve(id, index, &vesting.token_id).then(callback());
+                InstructionOutcome::Pending
+            }
+            Instruction::CreateVesting { vesting } => {
+                if !self.internal_can_spend(vesting.total.0) {
+                    InstructionOutcome::Failed
+                } else {
+                    self.internal_create_vesting(vesting);
+                    InstructionOutcome::Succeeded
+                }
+            }
+            Instruction::RevokeVesting { vesting_id } => {
+                self.internal_revoke_vesting(*vesting_id);
+                InstructionOutcome::Succeeded
+            }
+        };
+        ExecutedInstruction {
+            instruction: instr,
+            outcome,
+        }
+    }
+
+    /// Returns the bonds that were not slashed or returned yet to the proposers.
+    fn internal_refund_bonds(&mut self, proposal: &mut Proposal) {
+        for p in proposal.versions.iter_mut().filter(|p| p.bond.0 > 0) {
+            let bond = std::mem::replace(&mut p.bond, U128(0));
+            self.locked_amount -= bond.0;
+            Promise::new(p.proposer.clone()).transfer(bond.0);
+        }
+    }
+
+    /// Keeps the bond of the given version in the treasury.
+    fn internal_slash_bond(&mut self, proposal: &mut Proposal, version: u8) {
+        let bond = std::mem::replace(&mut proposal.versions[version as usize].bond, U128(0));
+        self.locked_amount -= bond.0;
+        self.slashed_amount += bond.0;
+    }
+
+    /// Process rejecting proposal.
+    pub(crate) fn internal_reject_proposal(&mut self, proposal: &mut Proposal) {
+        self.internal_release_conviction(proposal);
+        self.internal_refund_bonds(proposal);
+        for p in proposal.versions.iter() {
+            for instr in p.instructions.iter() {
+                match instr {
+                    Instruction::BountyDone {
+                        bounty_id,
+                        receiver_id,
+                    } => {
+                        self.internal_execute_bounty_payout(*bounty_id, receiver_id, false);
+                    },
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    pub(crate) fn internal_user_info(&self) -> UserInfo {
+        let account_id = env::predecessor_account_id();
+        UserInfo {
+            amount: self.get_user_weight(&account_id),
+            account_id,
+        }
+    }
+
+    /// Adds a proposal of the given kind with the given versions, locking their bonds.
+    fn internal_add_proposal(
+        &mut self,
+        policy: &Policy,
+        kind: String,
+        versions: Vec<ProposalVersion>,
+        prerequisites: Vec<Prerequisite>,
+        moved_from: Option<ProposalLink>,
+    ) -> u64 {
+        let count = versions.len();
+        let p = Proposal {
+            versions,
             kind: kind.clone(),
             status: ProposalStatus::InProgress,
-            approve_count: vec![0],
+            approve_count: vec![0; count],
             reject_count: 0,
-            remove_count: vec![0],
-            remove_flag: vec![false],
+            abstain_count: 0,
+            role_tallies: HashMap::new(),
+            remove_count: vec![0; count],
+            remove_flag: vec![false; count],
             votes: HashMap::new(),
+            delegated_votes: HashMap::new(),
+            decided_amount: None,
             remove_votes: Vec::new(),
-            submission_time: WrappedTimestamp::from(env::block_timestamp())
+            submission_time: WrappedTimestamp::from(env::block_timestamp()),
+            voting_start: WrappedTimestamp::from(
+                env::block_timestamp()
+                    + policy
+                        .get_discussion_period(&kind)
+                        .map(|period| period.0)
+                        .unwrap_or_default(),
+            ),
+            conviction_stakes: HashMap::new(),
+            convictions: vec![Conviction::new(env::block_timestamp()); count],
+            commitments: HashMap::new(),
+            prerequisites,
+            execution: vec![],
+            staged_policy: None,
+            moved_from,
+            moved_to: None,
         };
 
+        self.locked_amount += p.versions.iter().map(|v| v.bond.0).sum::<Balance>();
         let id = self.last_proposal_id;
         self.proposals
             .insert(&id, &VersionedProposal::Default(p.into()));
         self.last_proposal_id += 1;
         id
     }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Add proposal to this DAO.
+    #[payable]
+    pub fn propose(&mut self, description: String, instructions: Vec<Instruction>) -> u64 {
+        self.propose_with_options(description, instructions, ProposalOptions::default())
+    }
+
+    /// Add proposal with additional options to this DAO.
+    #[payable]
+    pub fn propose_with_options(
+        &mut self,
+        description: String,
+        instructions: Vec<Instruction>,
+        options: ProposalOptions,
+    ) -> u64 {
+        let policy = self.policy.get().unwrap().to_policy();
+        let kind = self.internal_check_proposal(&instructions);
+        if let Some(voted_amount) = &options.voted_amount {
+            voted_amount.assert_valid(&instructions);
+        }
+        for prerequisite in options.prerequisites.iter() {
+            assert!(
+                self.proposals.get(&prerequisite.proposal_id()).is_some(),
+                "ERR_NO_PREREQUISITE"
+            );
+        }
+
+        let version = ProposalVersion {
+            proposer: env::predecessor_account_id(),
+            instructions: instructions,
+            description: description,
+            voted_amount: options.voted_amount,
+            execute_after: options.execute_after,
+            bond: U128(policy.get_proposal_bond(&kind)),
+            atomic: options.atomic,
+        };
+        self.internal_add_proposal(&policy, kind, vec![version], options.prerequisites, None)
+    }
+
+    /// Accept a proposal moved from another DAO with `move_to_hub`. That DAO attaches
+    /// the bonds of the versions, which are returned to their proposers from here.
+    /// Only DAOs listed in `sub_daos` can move proposals here, so the proposers of the
+    /// versions are taken from the caller as they were recorded there.
+    #[payable]
+    pub fn accept_moved_proposal(&mut self, source_id: u64, versions: Vec<ProposalVersion>) -> u64 {
+        let policy = self.policy.get().unwrap().to_policy();
+        assert!(
+            policy.sub_daos.contains(&env::predecessor_account_id()),
+            "ERR_NOT_SUB_DAO"
+        );
+        assert!(!versions.is_empty(), "ERR_NO_PROPOSAL_VERSION");
+        // versions are indexed by a u8
+        assert!(versions.len() <= u8::MAX as usize, "ERR_TOO_MANY_VERSIONS");
+        let kind = self.internal_check_proposal(&versions[0].instructions);
+        if let Some(max_versions) = policy.get_max_versions(&kind) {
+            assert!(versions.len() <= max_versions as usize, "ERR_TOO_MANY_VERSIONS");
+        }
+        for version in versions.iter() {
+            assert_eq!(
+                self.internal_check_proposal(&version.instructions),
+                kind,
+                "ERR_DIFFERENT_PROPOSAL_KIND"
+            );
+            assert!(version.bond.0 >= policy.get_proposal_bond(&kind), "ERR_MIN_BOND");
+            if let Some(voted_amount) = &version.voted_amount {
+                voted_amount.assert_valid(&version.instructions);
+            }
+        }
+        assert_eq!(
+            versions.iter().map(|v| v.bond.0).sum::<Balance>(),
+            env::attached_deposit(),
+            "ERR_WRONG_BOND"
+        );
+        let moved_from = ProposalLink {
+            dao_id: env::predecessor_account_id(),
+            proposal_id: source_id,
+        };
+        self.internal_add_proposal(&policy, kind, versions, vec![], Some(moved_from))
+    }
+
+    /// Move a proposal to another DAO, e.g. to escalate it when it exceeds the mandate
+    /// of this one. The versions that weren't removed are proposed there, with their
+    /// bonds attached. The proposal is `Moved`, and is back in progress if refused.
+    pub fn move_to_hub(&mut self, id: u64, hub_id: ValidAccountId) -> Promise {
+        let mut proposal: Proposal = self.proposals.get(&id).expect("ERR_NO_PROPOSAL").into();
+        let policy = self.policy.get().unwrap().to_policy();
+
+        let allowed = policy.can_execute_action(
+            self.internal_user_info(),
+            &proposal.kind,
+            &Action::MoveToHub
+        );
+        assert!(allowed, "ERR_PERMISSION_DENIED");
+        assert_eq!(
+            proposal.status,
+            ProposalStatus::InProgress,
+            "ERR_PROPOSAL_NOT_IN_PROGRESS"
+        );
+        let versions = proposal.standing_versions();
+        assert!(!versions.is_empty(), "ERR_NO_PROPOSAL_VERSION");
+        let bonds: Balance = versions.iter().map(|v| v.bond.0).sum();
+        self.locked_amount -= bonds;
+        proposal.status = ProposalStatus::Moved;
+        self.proposals
+                .insert(&id, &VersionedProposal::Default(proposal));
+        ext_hub::accept_moved_proposal(
+            id,
+            versions,
+            hub_id.as_ref(),
+            bonds,
+            GAS_FOR_ACCEPT_MOVED_PROPOSAL,
+        )
+        .then(ext_self::on_proposal_moved(
+            id,
+            hub_id.into(),
+            &env::current_account_id(),
+            0,
+            GAS_FOR_MOVE_CALLBACK,
+        ))
+    }
+
+    /// Records the outcome of moving a proposal to another DAO.
+    #[private]
+    pub fn on_proposal_moved(&mut self, id: u64, hub_id: AccountId) {
+        let mut proposal: Proposal = self.proposals.get(&id).expect("ERR_NO_PROPOSAL").into();
+        let moved_id = promise_result_as_success()
+            .and_then(|result| near_sdk::serde_json::from_slice::<u64>(&result).ok());
+        match moved_id {
+            Some(proposal_id) => {
+                self.internal_release_conviction(&proposal);
+                // the bonds of the moved versions are held by the hub now.
+                for (version, removed) in proposal.versions.iter_mut().zip(proposal.remove_flag.iter()) {
+                    if !removed {
+                        version.bond = U128(0);
+                    }
+                }
+                self.internal_refund_bonds(&mut proposal);
+                proposal.moved_to = Some(ProposalLink {
+                    dao_id: hub_id,
+                    proposal_id,
+                });
+            }
+            None => {
+                // the bonds are refunded along with the failed call.
+                let bonds: Balance = proposal.standing_versions().iter().map(|v| v.bond.0).sum();
+                self.locked_amount += bonds;
+                proposal.status = ProposalStatus::InProgress;
+            }
+        }
+        self.proposals
+                .insert(&id, &VersionedProposal::Default(proposal));
+        self.internal_resolve_dependents(id);
+    }
 
     /// Adds a counter proposal to an existing one. Voters can only vote for one of these versions
     #[payable]
     pub fn counter_propose(&mut self, id: u64, description: String, instructions: Vec<Instruction>) -> u8 {
+        self.counter_propose_with_options(id, description, instructions, ProposalOptions::default())
+    }
+
+    /// Adds a counter proposal with additional options to an existing one. Prerequisites
+    /// are shared by all versions and can't be set here.
+    #[payable]
+    pub fn counter_propose_with_options(
+        &mut self,
+        id: u64,
+        description: String,
+        instructions: Vec<Instruction>,
+        options: ProposalOptions,
+    ) -> u8 {
         let mut p: Proposal = self.proposals.get(&id).expect("ERR_NO_PROPOSAL").into();
         
         // the new proposal must be of the same proposal_kind
         let kind = self.internal_check_proposal(&instructions);
         assert_eq!(kind, p.kind, "ERR_DIFFERENT_PROPOSAL_KIND");
+        assert!(options.prerequisites.is_empty(), "ERR_PREREQUISITES_PER_PROPOSAL");
+        if let Some(voted_amount) = &options.voted_amount {
+            voted_amount.assert_valid(&instructions);
+        }
+        assert!(!p.versions_frozen(), "ERR_VERSIONS_FROZEN");
+        let policy = self.policy.get().unwrap().to_policy();
+        if let Some(max_versions) = policy.get_max_versions(&kind) {
+            assert!(p.versions.len() < max_versions as usize, "ERR_TOO_MANY_VERSIONS");
+        }
 
         // add the new proposal version, update the tallies and return the corresponding version
         p.versions.push(ProposalVersion{
             proposer: env::predecessor_account_id(),
             instructions,
             description,
+            voted_amount: options.voted_amount,
+            execute_after: options.execute_after,
+            bond: U128(policy.get_proposal_bond(&kind)),
+            atomic: options.atomic,
         });
+        self.locked_amount += policy.get_proposal_bond(&kind);
         p.approve_count.push(0);
+        for tally in p.role_tallies.values_mut() {
+            tally.approve_count.push(0);
+        }
         p.remove_count.push(0);
         p.remove_flag.push(false);
+        p.convictions.push(Conviction::new(env::block_timestamp()));
+        // versions are indexed by a u8
+        assert!(p.versions.len() <= u8::MAX as usize, "ERR_TOO_MANY_VERSIONS");
         
         let version: u8 = (p.versions.len() - 1) as u8;
         self.proposals.insert(&id, &VersionedProposal::Default(p));
@@ -479,12 +1830,56 @@ impl Contract {
 
     // Approve a proposal
     pub fn approve(&mut self, id: u64, version: u8) {
-        self.handle_vote(id, version + 1)
+        self.handle_vote(
+            id,
+            VoteChoice::Approve(version),
+            BallotKind::Single,
+            vec![],
+            HashMap::new(),
+            None,
+        )
+    }
+
+    /// Approve a version of a proposal whose amount is chosen by the voters,
+    /// voting for the given amount.
+    pub fn approve_amount(&mut self, id: u64, version: u8, amount: U128) {
+        self.handle_vote(
+            id,
+            VoteChoice::Approve(version),
+            BallotKind::Single,
+            vec![],
+            vec![(version, amount)].into_iter().collect(),
+            None,
+        )
     }
 
     // Reject a proposal
     pub fn reject(&mut self, id: u64) {
-        self.handle_vote(id, 0)
+        self.handle_vote(id, VoteChoice::Reject, BallotKind::Single, vec![], HashMap::new(), None)
+    }
+
+    /// Abstain from voting on a proposal. The vote counts toward the turnout but
+    /// neither approves nor rejects the proposal.
+    pub fn abstain(&mut self, id: u64) {
+        self.handle_vote(id, VoteChoice::Abstain, BallotKind::Single, vec![], HashMap::new(), None)
+    }
+
+    /// Vote for versions of a proposal in order of preference. Only available to
+    /// proposals whose vote policy uses ranked ballots. `amounts` are the amounts chosen
+    /// for the ranked versions with a voted amount.
+    pub fn vote_ranked(&mut self, id: u64, ranking: Vec<u8>, amounts: HashMap<u8, U128>) {
+        assert!(!ranking.is_empty(), "ERR_EMPTY_BALLOT");
+        let choice = VoteChoice::Approve(ranking[0]);
+        self.handle_vote(id, choice, BallotKind::Ranked, ranking, amounts, None)
+    }
+
+    /// Approve several versions of a proposal at once. Only available to
+    /// proposals whose vote policy uses approval ballots. `amounts` are the amounts
+    /// chosen for the approved versions with a voted amount.
+    pub fn vote_approval(&mut self, id: u64, versions: Vec<u8>, amounts: HashMap<u8, U128>) {
+        assert!(!versions.is_empty(), "ERR_EMPTY_BALLOT");
+        let choice = VoteChoice::Approve(versions[0]);
+        self.handle_vote(id, choice, BallotKind::Approval, versions, amounts, None)
     }
 
     pub fn withdraw(&mut self, id: u64, version: u8) {
@@ -514,8 +1909,7 @@ impl Contract {
         );
 
         // No one should have voted on the proposal yet
-        assert_eq!(proposal.approve_count[version as usize], 0, "ERR_VOTING_BEGUN");
-        assert_eq!(proposal.remove_count[version as usize], 0, "ERR_VOTING_BEGUN");
+        assert!(!proposal.has_votes(version), "ERR_VOTING_BEGUN");
         proposal.remove_flag[version as usize] = true;
 
         self.proposals
@@ -541,27 +1935,31 @@ impl Contract {
         
         let threshold = policy.get_threshold(
             vote_policy,
-            self.total_delegation_amount,
+            self.internal_total_supply(&policy, vote_policy, proposal.submission_time.0),
             &proposal.kind,
         );
-        let weight = self.get_user_weight(&sender_id);
+        let weight = self.internal_vote_power(&policy, vote_policy, &sender_id, proposal.submission_time.0);
         let remove_vote = RemoveVote {
             account_id: sender_id,
             version: version,
         };
 
-        proposal.remove_flag[version as usize] = proposal.update_remove_votes(
+        let removed = proposal.update_remove_votes(
             remove_vote,
             weight,
             threshold,
         );
+        if removed && !proposal.remove_flag[version as usize] {
+            self.internal_slash_bond(&mut proposal, version);
+        }
+        proposal.remove_flag[version as usize] = removed;
 
         self.proposals
                 .insert(&id, &VersionedProposal::Default(proposal));
     }
 
     pub fn remove(&mut self, id: u64) {
-        let proposal: Proposal = self.proposals.get(&id).expect("ERR_NO_PROPOSAL").into();
+        let mut proposal: Proposal = self.proposals.get(&id).expect("ERR_NO_PROPOSAL").into();
         let policy = self.policy.get().unwrap().to_policy();
 
         // Check permissions for the given action
@@ -577,7 +1975,124 @@ impl Contract {
             "ERR_PROPOSAL_NOT_IN_PROGRESS"
         );
 
+        self.internal_release_conviction(&proposal);
+        for version in 0..proposal.versions.len() {
+            self.internal_slash_bond(&mut proposal, version as u8);
+        }
         self.proposals.remove(&id);
+        self.internal_resolve_dependents(id);
+    }
+
+    /// Execute a queued proposal once its execution delay has passed, the time it was
+    /// scheduled for has come and its prerequisites are met. Anyone can call this.
+    pub fn execute(&mut self, id: u64) {
+        let mut proposal: Proposal = self.proposals.get(&id).expect("ERR_NO_PROPOSAL").into();
+        let policy = self.policy.get().unwrap().to_policy();
+        let version = match proposal.status {
+            ProposalStatus::Queued { version, execute_after, awaiting_prerequisites } => {
+                assert!(
+                    env::block_timestamp() >= execute_after.0,
+                    "ERR_EXECUTION_DELAY_NOT_PASSED"
+                );
+                assert!(!awaiting_prerequisites, "ERR_PREREQUISITES_NOT_MET");
+                version
+            }
+            _ => env::panic(b"ERR_PROPOSAL_NOT_QUEUED"),
+        };
+        self.queued_proposals.remove(&id);
+        self.internal_execute_proposal(id, &policy, &mut proposal, version);
+        self.proposals
+                .insert(&id, &VersionedProposal::Default(proposal));
+        self.internal_resolve_dependents(id);
+    }
+
+    /// Cancel a queued proposal before it is executed.
+    pub fn cancel(&mut self, id: u64) {
+        let mut proposal: Proposal = self.proposals.get(&id).expect("ERR_NO_PROPOSAL").into();
+        let policy = self.policy.get().unwrap().to_policy();
+
+        let allowed = policy.can_execute_action(
+            self.internal_user_info(),
+            &proposal.kind,
+            &Action::CancelProposal
+        );
+        assert!(allowed, "ERR_PERMISSION_DENIED");
+        assert!(
+            matches!(proposal.status, ProposalStatus::Queued { .. }),
+            "ERR_PROPOSAL_NOT_QUEUED"
+        );
+        self.queued_proposals.remove(&id);
+        self.internal_reject_proposal(&mut proposal);
+        proposal.status = ProposalStatus::Cancelled;
+        self.proposals
+                .insert(&id, &VersionedProposal::Default(proposal));
+        self.internal_resolve_dependents(id);
+    }
+
+    /// Records the outcome of an instruction of the given proposal that ran through a promise.
+    #[private]
+    pub fn on_instruction_executed(&mut self, id: u64, index: u64) {
+        let mut proposal: Proposal = self.proposals.get(&id).expect("ERR_NO_PROPOSAL").into();
+        let policy = self.policy.get().unwrap().to_policy();
+        proposal.execution[index as usize].outcome = if is_promise_success() {
+            InstructionOutcome::Succeeded
+        } else {
+            InstructionOutcome::Failed
+        };
+        self.internal_update_execution(id, &policy, &mut proposal);
+        self.proposals
+                .insert(&id, &VersionedProposal::Default(proposal));
+        self.internal_resolve_dependents(id);
+    }
+
+    /// Run the failed instructions of a proposal again, along with the reverted ones
+    /// of atomic proposals.
+    pub fn retry(&mut self, id: u64) {
+        let mut proposal: Proposal = self.proposals.get(&id).expect("ERR_NO_PROPOSAL").into();
+        let policy = self.policy.get().unwrap().to_policy();
+
+        let allowed = policy.can_execute_action(
+            self.internal_user_info(),
+            &proposal.kind,
+            &Action::RetryExecution
+        );
+        assert!(allowed, "ERR_PERMISSION_DENIED");
+        let version = match proposal.status {
+            ProposalStatus::Failed { version } => version,
+            _ => env::panic(b"ERR_PROPOSAL_NOT_FAILED"),
+        };
+        let atomic = proposal.versions[version as usize].atomic;
+        let retried: Vec<usize> = (0..proposal.execution.len())
+            .filter(|index| {
+                matches!(
+                    proposal.execution[*index].outcome,
+                    InstructionOutcome::Failed | InstructionOutcome::Reverted
+                )
+            })
+            .collect();
+        let mut reserved_gas = self.internal_assert_gas(
+            retried.iter().map(|index| &proposal.execution[*index].instruction),
+        );
+        if atomic {
+            proposal.staged_policy = Some(self.internal_policy_hash());
+        }
+        for index in retried {
+            let instr = proposal.execution[index].instruction.clone();
+            reserved_gas -= self.internal_instruction_gas(&instr);
+            proposal.execution[index] = self.internal_execute_instruction(
+                id,
+                index as u64,
+                &policy,
+                instr,
+                atomic,
+                reserved_gas,
+            );
+        }
+        proposal.status = ProposalStatus::Approved { version };
+        self.internal_update_execution(id, &policy, &mut proposal);
+        self.proposals
+                .insert(&id, &VersionedProposal::Default(proposal));
+        self.internal_resolve_dependents(id);
     }
 
     pub fn finalize(&mut self, id: u64) {
@@ -596,16 +2111,37 @@ impl Contract {
             "ERR_PROPOSAL_NOT_IN_PROGRESS"
         );
 
+        if policy.proposal_phase(&proposal) == ProposalPhase::Closed {
+            self.internal_tally_unrevealed(&policy, &mut proposal);
+        }
+        let vote_policy = policy
+            .get_vote_policy(&proposal.kind)
+            .unwrap_or(&policy.default_vote_policy);
+        let submission_time = proposal.submission_time.0;
         proposal.status = policy.proposal_status(
             &proposal,
-            self.total_delegation_amount,
-        );
-        assert_eq!(
-            proposal.status,
-            ProposalStatus::Expired,
-            "ERR_PROPOSAL_NOT_EXPIRED"
+            self.internal_total_supply(&policy, vote_policy, submission_time),
+            |account_id| {
+                vote_policy.vote_weight(
+                    self.internal_vote_power(&policy, vote_policy, account_id, submission_time),
+                )
+            },
         );
-        self.internal_reject_proposal(&policy, &proposal);
+        if let (ProposalStatus::InProgress, Some(conviction_policy)) = (&proposal.status, &vote_policy.conviction) {
+            proposal.status = self.internal_conviction_status(conviction_policy, &proposal);
+        }
+        match proposal.status {
+            ProposalStatus::Approved{ version } => {
+                self.internal_approve_proposal(id, &policy, &mut proposal, version);
+            },
+            ProposalStatus::Rejected | ProposalStatus::Expired => {
+                self.internal_reject_proposal(&mut proposal);
+            },
+            _ => env::panic(b"ERR_PROPOSAL_NOT_EXPIRED"),
+        }
+        self.proposals
+                .insert(&id, &VersionedProposal::Default(proposal));
+        self.internal_resolve_dependents(id);
     }
 
     pub fn amend(&mut self, id: u64, version: u8, description: String, instructions: Vec<Instruction>) {
@@ -628,32 +2164,55 @@ impl Contract {
         // the new proposal must be of the same proposal_kind
         let kind = self.internal_check_proposal(&instructions);
         assert_eq!(kind, proposal.kind, "ERR_DIFFERENT_PROPOSAL_KIND");
+        assert!(!proposal.versions_frozen(), "ERR_VERSIONS_FROZEN");
 
         assert!(version < proposal.versions.len() as u8, "ERR_NO_PROPOSAL_VERSION");
         assert_eq!(proposal.versions[version as usize].proposer, env::predecessor_account_id(), "ERR_UNAUTHORIZED_AMEND");
         // No one should have voted on the proposal yet
-        assert_eq!(proposal.approve_count[version as usize], 0, "ERR_VOTING_BEGUN");
-        assert_eq!(proposal.remove_count[version as usize], 0, "ERR_VOTING_BEGUN");
+        assert!(!proposal.has_votes(version), "ERR_VOTING_BEGUN");
         
+        // a voted amount carries over to the amended instructions
+        let voted_amount = proposal.versions[version as usize].voted_amount.clone();
+        if let Some(voted_amount) = &voted_amount {
+            voted_amount.assert_valid(&instructions);
+        }
         proposal.versions[version as usize] = ProposalVersion {
             proposer: env::predecessor_account_id(),
             description,
-            instructions
+            instructions,
+            voted_amount,
+            execute_after: proposal.versions[version as usize].execute_after,
+            bond: proposal.versions[version as usize].bond,
+            atomic: proposal.versions[version as usize].atomic,
         };
         self.proposals
                 .insert(&id, &VersionedProposal::Default(proposal));
     }
 
-    fn handle_vote(&mut self, id: u64, choice: u8) {
+    /// Records the vote of the caller. Plain approve and reject votes are cast as
+    /// `BallotKind::Single` and are accepted by all vote policies. Under secret
+    /// ballots votes are only accepted when revealed with the `salt` of their commitment.
+    pub(crate) fn handle_vote(
+        &mut self,
+        id: u64,
+        choice: VoteChoice,
+        ballot_kind: BallotKind,
+        versions: Vec<u8>,
+        amounts: HashMap<u8, U128>,
+        salt: Option<String>,
+    ) {
         let mut proposal: Proposal = self.proposals.get(&id).expect("ERR_NO_PROPOSAL").into();
         let policy = self.policy.get().unwrap().to_policy();
-        assert!(choice <= proposal.versions.len() as u8, "ERR_INVALID_CHOICE");
+        if let VoteChoice::Approve(version) = choice {
+            assert!((version as usize) < proposal.versions.len(), "ERR_INVALID_CHOICE");
+        }
 
         // Check permissions for the given action
-        let mut action = Action::VoteApprove;
-        if choice == 0 {
-            action = Action::VoteReject;
-        }
+        let action = match choice {
+            VoteChoice::Approve(_) => Action::VoteApprove,
+            VoteChoice::Reject => Action::VoteReject,
+            VoteChoice::Abstain => Action::VoteAbstain,
+        };
         let allowed = policy.can_execute_action(self.internal_user_info(), &proposal.kind, &action);
         assert!(allowed, "ERR_PERMISSION_DENIED");
         assert_eq!(
@@ -664,35 +2223,81 @@ impl Contract {
         let vote_policy = policy
             .get_vote_policy(&proposal.kind)
             .unwrap_or(&policy.default_vote_policy);
+        assert!(
+            ballot_kind == BallotKind::Single || ballot_kind == vote_policy.ballot_kind,
+            "ERR_WRONG_BALLOT_KIND"
+        );
+        assert_ne!(policy.proposal_phase(&proposal), ProposalPhase::Discussion, "ERR_DISCUSSION_PHASE");
+        assert!(
+            !matches!(choice, VoteChoice::Approve(_)) || vote_policy.conviction.is_none(),
+            "ERR_CONVICTION_VOTING"
+        );
         let sender_id = env::predecessor_account_id();
+        if vote_policy.secret_ballot.is_some() {
+            let salt = salt.expect("ERR_SECRET_BALLOT");
+            assert_eq!(policy.proposal_phase(&proposal), ProposalPhase::Reveal, "ERR_NOT_REVEAL_PHASE");
+            let commitment = proposal.commitments.remove(&sender_id).expect("ERR_NO_COMMITMENT");
+            assert_eq!(
+                commitment,
+                vote_hash(&sender_id, choice, &versions, &amounts, &salt),
+                "ERR_WRONG_REVEAL"
+            );
+        }
+
+        // voting directly overrides the delegation of the vote
+        proposal.remove_delegated_weight(&sender_id);
 
         // create the vote - this also checks that the proposal version exists
-        let vote = proposal.create_vote(
+        let mut vote = proposal.create_vote(
             &vote_policy,
             choice, 
-            self.get_user_weight(&sender_id)
+            versions,
+            amounts,
+            // voting power is taken at the time the proposal was submitted
+            self.internal_vote_power(&policy, vote_policy, &sender_id, proposal.submission_time.0)
         );
+        if !proposal.votes.contains_key(&sender_id) {
+            vote.delegated =
+                self.internal_delegated_weights(&policy, &proposal, &sender_id, &action);
+            vote.weight += vote.delegated.iter().map(|d| d.weight).sum::<Balance>();
+        }
+        vote.houses = policy.user_houses(vote_policy, self.internal_user_info());
 
-        let threshold = policy.get_threshold(
+        let total_supply =
+            self.internal_total_supply(&policy, vote_policy, proposal.submission_time.0);
+        let total_weight = policy.get_total_weight(
             &vote_policy,
-            self.total_delegation_amount, 
+            total_supply, 
             &proposal.kind,
         );
+        let submission_time = proposal.submission_time.0;
+        let house_thresholds =
+            policy.house_thresholds(vote_policy, total_supply, &proposal.kind, |account_id| {
+                vote_policy.vote_weight(
+                    self.internal_vote_power(&policy, vote_policy, account_id, submission_time),
+                )
+            });
 
         // update the votes and check if the status of the proposal has changed
-        proposal.status = proposal.update_votes(
+        let status = proposal.update_votes(
             &sender_id,
             vote,
-            threshold
+            &vote_policy,
+            total_weight,
+            &house_thresholds,
         );
+        // secret ballots are only tallied once the reveal period is over
+        if vote_policy.secret_ballot.is_none() {
+            proposal.status = status;
+        }
         match proposal.status {
             ProposalStatus::Approved{ version } => { 
                 // success, now execute the proposal
-                self.internal_execute_proposal(&policy, &proposal, &proposal.versions[version as usize]);
+                self.internal_approve_proposal(id, &policy, &mut proposal, version);
             },
             ProposalStatus::Rejected => {
                 // defeated, return the bond
-                self.internal_reject_proposal(&policy, &proposal)
+                self.internal_reject_proposal(&mut proposal)
             }
             _ => {}, 
         };
@@ -700,14 +2305,11 @@ impl Contract {
         // update the proposal
         self.proposals
                 .insert(&id, &VersionedProposal::Default(proposal));
+        self.internal_resolve_dependents(id);
     }
 
     fn internal_check_proposal(&mut self, instructions: &Vec<Instruction>) -> String {
         let policy = self.policy.get().unwrap().to_policy();
-        assert!(
-            env::attached_deposit() >= policy.proposal_bond.0,
-            "ERR_MIN_BOND"
-        );
 
         // 1. validate proposal.
         assert!(instructions.len() > 0, "ERR_EMPTY_INSTRUCTION_SET");
@@ -720,9 +2322,36 @@ impl Contract {
             // TODO: add more verifications.
             _ => {}
         };
+        for instr in instructions.iter() {
+            match instr {
+                Instruction::CreateRecurringPayment { payment } => payment.assert_valid(),
+                Instruction::CancelRecurringPayment { payment_id } => {
+                    assert!(self.payments.get(payment_id).is_some(), "ERR_NO_PAYMENT")
+                }
+                Instruction::CreateVesting { vesting } => vesting.assert_valid(),
+                Instruction::ChangePolicy { policy: new_policy } => {
+                    new_policy.clone().upgrade().to_policy().assert_valid_templates();
+                    assert!(
+                        self.internal_keeps_lock_duration(&policy, new_policy),
+                        "ERR_LOCK_DURATION_FIXED"
+                    );
+                }
+                _ => {}
+            }
+        }
 
         // 2. check permission of caller to add proposal.
         let kind = policy.match_proposal_kind(&instructions);
+        assert!(
+            env::attached_deposit() >= policy.get_proposal_bond(&kind),
+            "ERR_MIN_BOND"
+        );
+        let vote_policy = policy
+            .get_vote_policy(&kind)
+            .unwrap_or(&policy.default_vote_policy);
+        if vote_policy.conviction.is_some() {
+            self.internal_check_conviction_instructions(instructions);
+        }
         assert!(
             policy
                 .can_execute_action(
@@ -753,3 +2382,978 @@ impl Contract {
         true
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, MockedBlockchain, PromiseResult};
+    use near_sdk_sim::to_yocto;
+
+    use crate::policy::{House, WeightKind, WeightOrRatio};
+    use crate::test_utils::*;
+
+    use super::*;
+
+    #[test]
+    fn test_lazy_consensus() {
+        let mut context = VMContextBuilder::new();
+        let mut policy = VersionedPolicy::Default(vec![
+            accounts(1).into(),
+            accounts(2).into(),
+            accounts(3).into(),
+        ])
+        .upgrade();
+        policy.to_policy_mut().default_vote_policy.objection_threshold =
+            Some(WeightOrRatio::Weight(U128(1)));
+        let mut contract = new_contract(&mut context, policy);
+        let unopposed = create_proposal(&mut context, &mut contract);
+        let opposed = create_proposal(&mut context, &mut contract);
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.reject(opposed);
+        assert_eq!(
+            contract.get_proposal(opposed).proposal.status,
+            ProposalStatus::InProgress
+        );
+
+        testing_env!(context
+            .block_timestamp(1_000_000_000 * 24 * 60 * 60 * 8)
+            .build());
+        contract.finalize(unopposed);
+        assert_eq!(
+            contract.get_proposal(unopposed).proposal.status,
+            ProposalStatus::Approved{ version: 0 }
+        );
+        contract.finalize(opposed);
+        assert_eq!(
+            contract.get_proposal(opposed).proposal.status,
+            ProposalStatus::Rejected
+        );
+    }
+
+    #[test]
+    fn test_participation_quorum() {
+        let mut context = VMContextBuilder::new();
+        let mut policy = VersionedPolicy::Default(vec![
+            accounts(1).into(),
+            accounts(2).into(),
+            accounts(3).into(),
+            accounts(4).into(),
+        ])
+        .upgrade();
+        policy.to_policy_mut().default_vote_policy.quorum_kind =
+            QuorumKind::Participation(WeightOrRatio::Ratio(1, 2));
+        let mut contract = new_contract(&mut context, policy);
+        let id = create_proposal(&mut context, &mut contract);
+
+        // the quorum is 3 votes, after which half of the votes cast are needed.
+        contract.approve(id, 0);
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.reject(id);
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.approve(id, 0);
+        // the last member could still tie the vote.
+        assert_eq!(
+            contract.get_proposal(id).proposal.status,
+            ProposalStatus::InProgress
+        );
+
+        testing_env!(context
+            .block_timestamp(1_000_000_000 * 24 * 60 * 60 * 8)
+            .build());
+        contract.finalize(id);
+        assert_eq!(
+            contract.get_proposal(id).proposal.status,
+            ProposalStatus::Approved{ version: 0 }
+        );
+    }
+
+    #[test]
+    fn test_abstain() {
+        let mut context = VMContextBuilder::new();
+        let mut policy = VersionedPolicy::Default(vec![
+            accounts(1).into(),
+            accounts(2).into(),
+            accounts(3).into(),
+            accounts(4).into(),
+        ])
+        .upgrade();
+        policy.to_policy_mut().default_vote_policy.quorum_kind =
+            QuorumKind::Participation(WeightOrRatio::Ratio(1, 2));
+        let mut contract = new_contract(&mut context, policy);
+        let id = create_proposal(&mut context, &mut contract);
+
+        // the abstention counts toward the quorum of 3 votes.
+        contract.abstain(id);
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.approve(id, 0);
+        let proposal = contract.get_proposal(id).proposal;
+        assert_eq!(proposal.abstain_count, 1);
+        assert_eq!(proposal.reject_count, 0);
+        assert_eq!(proposal.status, ProposalStatus::InProgress);
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.approve(id, 0);
+        assert_eq!(
+            contract.get_proposal(id).proposal.status,
+            ProposalStatus::InProgress
+        );
+        // 3 of the 4 members approving decide the vote before it ends.
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        contract.approve(id, 0);
+        assert_eq!(
+            contract.get_proposal(id).proposal.status,
+            ProposalStatus::Approved{ version: 0 }
+        );
+    }
+
+    fn bicameral_contract(context: &mut VMContextBuilder, weight_kind: WeightKind) -> Contract {
+        let mut policy = VersionedPolicy::Default(vec![]).upgrade();
+        let group = |members: Vec<usize>| {
+            RoleKind::Group(members.into_iter().map(|i| accounts(i).into()).collect())
+        };
+        policy.to_policy_mut().roles = vec![
+            RolePermission {
+                name: "council".to_string(),
+                kind: group(vec![1, 2]),
+                permissions: vec!["*:*".to_string()].into_iter().collect(),
+            },
+            RolePermission {
+                name: "community".to_string(),
+                kind: group(vec![1, 3, 4]),
+                permissions: vec!["*:*".to_string()].into_iter().collect(),
+            },
+        ];
+        policy.to_policy_mut().default_vote_policy.houses = ["council", "community"]
+            .iter()
+            .map(|role| House {
+                role: role.to_string(),
+                threshold: WeightOrRatio::Ratio(1, 2),
+            })
+            .collect();
+        policy.to_policy_mut().default_vote_policy.weight_kind = weight_kind;
+        new_contract(context, policy)
+    }
+
+    #[test]
+    fn test_bicameral_vote() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = bicameral_contract(&mut context, WeightKind::RoleWeight);
+        let id = create_proposal(&mut context, &mut contract);
+
+        // each house needs 2 approvals.
+        for i in 2..4 {
+            testing_env!(context.predecessor_account_id(accounts(i)).build());
+            contract.approve(id, 0);
+        }
+        let proposal = contract.get_proposal(id).proposal;
+        assert_eq!(proposal.approve_count[0], 2);
+        assert_eq!(proposal.role_tallies["council"].approve_count[0], 1);
+        assert_eq!(proposal.role_tallies["community"].approve_count[0], 1);
+        assert_eq!(proposal.status, ProposalStatus::InProgress);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.approve(id, 0);
+        assert_eq!(
+            contract.get_proposal(id).proposal.status,
+            ProposalStatus::Approved{ version: 0 }
+        );
+    }
+
+    #[test]
+    fn test_bicameral_delegated_vote() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = bicameral_contract(&mut context, WeightKind::RoleWeight);
+        testing_env!(context
+            .predecessor_account_id(accounts(4))
+            .attached_deposit(to_yocto("0.01"))
+            .build());
+        contract.delegate_vote(accounts(2), None);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        let id = create_proposal(&mut context, &mut contract);
+
+        // the weight of the delegator only counts in the community house.
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.approve(id, 0);
+        let proposal = contract.get_proposal(id).proposal;
+        assert_eq!(proposal.approve_count[0], 2);
+        assert_eq!(proposal.role_tallies["council"].approve_count[0], 1);
+        assert_eq!(proposal.role_tallies["community"].approve_count[0], 1);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.approve(id, 0);
+        assert_eq!(
+            contract.get_proposal(id).proposal.status,
+            ProposalStatus::Approved{ version: 0 }
+        );
+    }
+
+    #[test]
+    fn test_bicameral_token_weight() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = bicameral_contract(&mut context, WeightKind::TokenWeight);
+        for (i, amount) in [(1, 100), (2, 100), (3, 10), (4, 10)] {
+            contract.delegations.insert(&accounts(i).into(), &amount);
+        }
+        contract.total_delegation_amount = 1_000;
+        let id = create_proposal(&mut context, &mut contract);
+
+        // the council needs 101 out of 200 and the community 61 out of 120.
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.approve(id, 0);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.approve(id, 0);
+        assert_eq!(
+            contract.get_proposal(id).proposal.status,
+            ProposalStatus::Approved{ version: 0 }
+        );
+    }
+
+    fn delayed_contract(context: &mut VMContextBuilder) -> Contract {
+        let mut policy = VersionedPolicy::Default(vec![accounts(1).into()]).upgrade();
+        let kind: ProposalKind = near_sdk::serde_json::from_value(near_sdk::serde_json::json!({
+            "name": "delayed",
+            "required_instrs": [],
+            "vote_policy": VotePolicy::default(),
+            "execution_delay": "100",
+        }))
+        .unwrap();
+        policy.to_policy_mut().proposal_kinds.push(kind);
+        new_contract(context, policy)
+    }
+
+    #[test]
+    fn test_execution_delay() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = delayed_contract(&mut context);
+        let id = create_proposal(&mut context, &mut contract);
+        contract.approve(id, 0);
+        assert_eq!(
+            contract.get_proposal(id).proposal.status,
+            ProposalStatus::Queued{ version: 0, execute_after: 100.into(), awaiting_prerequisites: false }
+        );
+        testing_env!(context.block_timestamp(100).build());
+        contract.execute(id);
+        assert_eq!(
+            contract.get_proposal(id).proposal.status,
+            ProposalStatus::Approved{ version: 0 }
+        );
+
+        let id = create_proposal(&mut context, &mut contract);
+        contract.approve(id, 0);
+        contract.cancel(id);
+        assert_eq!(
+            contract.get_proposal(id).proposal.status,
+            ProposalStatus::Cancelled
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_EXECUTION_DELAY_NOT_PASSED")]
+    fn test_execute_before_delay() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = delayed_contract(&mut context);
+        let id = create_proposal(&mut context, &mut contract);
+        contract.approve(id, 0);
+        testing_env!(context.block_timestamp(99).build());
+        contract.execute(id);
+    }
+
+    #[test]
+    fn test_scheduled_execution() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .account_balance(to_yocto("1000"))
+            .attached_deposit(to_yocto("1"))
+            .build());
+        let mut contract = Contract::new(
+            Config::test_config(),
+            VersionedPolicy::Default(vec![accounts(1).into()]),
+        );
+        let id = contract.propose_with_options(
+            "pay on the 1st".to_string(),
+            vec![Instruction::Transfer {
+                token_id: BASE_TOKEN.to_string(),
+                receiver_id: accounts(2).into(),
+                amount: U128(to_yocto("100")),
+            }],
+            ProposalOptions {
+                execute_after: Some(500.into()),
+                ..ProposalOptions::default()
+            },
+        );
+        contract.approve(id, 0);
+        assert_eq!(
+            contract.get_proposal(id).proposal.status,
+            ProposalStatus::Queued{ version: 0, execute_after: 500.into(), awaiting_prerequisites: false }
+        );
+        assert_eq!(contract.get_queued_proposals(0, 10).len(), 1);
+        testing_env!(context.block_timestamp(500).build());
+        contract.execute(id);
+        assert!(contract.get_queued_proposals(0, 10).is_empty());
+    }
+
+    #[test]
+    fn test_queued_proposals_paging() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .account_balance(to_yocto("1000"))
+            .attached_deposit(to_yocto("1"))
+            .build());
+        let mut contract = Contract::new(
+            Config::test_config(),
+            VersionedPolicy::Default(vec![accounts(1).into()]),
+        );
+        let scheduled = ProposalOptions {
+            execute_after: Some(500.into()),
+            ..ProposalOptions::default()
+        };
+        let executed = contract.propose("now".to_string(), transfer_to(accounts(2).into()));
+        contract.approve(executed, 0);
+        // a counter proposal can be scheduled on its own.
+        let countered = contract.propose("now".to_string(), transfer_to(accounts(2).into()));
+        contract.counter_propose_with_options(
+            countered,
+            "later".to_string(),
+            transfer_to(accounts(3).into()),
+            scheduled.clone(),
+        );
+        contract.approve(countered, 1);
+        let queued = contract.propose_with_options(
+            "later".to_string(),
+            transfer_to(accounts(2).into()),
+            scheduled,
+        );
+        contract.approve(queued, 0);
+
+        assert_eq!(contract.get_queued_proposals_count(), 2);
+        assert_eq!(contract.get_queued_proposals(0, 1)[0].id, countered);
+        assert_eq!(contract.get_queued_proposals(1, 1)[0].id, queued);
+        assert!(contract.get_queued_proposals(2, 1).is_empty());
+
+        testing_env!(context.block_timestamp(500).build());
+        contract.execute(countered);
+        let page = contract.get_queued_proposals(0, 10);
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].id, queued);
+    }
+
+    #[test]
+    fn test_prerequisites() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = new_contract(
+            &mut context,
+            VersionedPolicy::Default(vec![accounts(1).into()]),
+        );
+        let first = create_proposal(&mut context, &mut contract);
+        let second = contract.propose_with_options(
+            "after the first".to_string(),
+            vec![Instruction::Transfer {
+                token_id: BASE_TOKEN.to_string(),
+                receiver_id: accounts(2).into(),
+                amount: U128(to_yocto("10")),
+            }],
+            ProposalOptions {
+                prerequisites: vec![Prerequisite::Executed(first)],
+                ..ProposalOptions::default()
+            },
+        );
+
+        // the second proposal is held until the first one is executed.
+        contract.approve(second, 0);
+        assert_eq!(
+            contract.get_proposal(second).proposal.status,
+            ProposalStatus::Queued{ version: 0, execute_after: 0.into(), awaiting_prerequisites: true }
+        );
+        contract.approve(first, 0);
+        resolve_instruction(&mut context, &mut contract, first, 0, true);
+        assert_eq!(
+            contract.get_proposal(second).proposal.status,
+            ProposalStatus::Queued{ version: 0, execute_after: 0.into(), awaiting_prerequisites: false }
+        );
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.execute(second);
+        assert_eq!(
+            contract.get_proposal(second).proposal.status,
+            ProposalStatus::Approved{ version: 0 }
+        );
+    }
+
+    #[test]
+    fn test_failed_prerequisite_rejects_dependents() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .account_balance(to_yocto("1000"))
+            .attached_deposit(to_yocto("1"))
+            .build());
+        let mut contract = Contract::new(
+            Config::test_config(),
+            VersionedPolicy::Default(vec![accounts(1).into()]),
+        );
+        let after = |contract: &mut Contract, prerequisite: Prerequisite| {
+            contract.propose_with_options(
+                "after".to_string(),
+                transfer_to(accounts(2).into()),
+                ProposalOptions {
+                    prerequisites: vec![prerequisite],
+                    ..ProposalOptions::default()
+                },
+            )
+        };
+        let first = contract.propose("first".to_string(), transfer_to(accounts(2).into()));
+        let second = after(&mut contract, Prerequisite::Executed(first));
+        let third = after(&mut contract, Prerequisite::Executed(second));
+        contract.approve(second, 0);
+        contract.approve(third, 0);
+        assert_eq!(contract.get_queued_proposals_count(), 2);
+        let locked = contract.locked_amount;
+
+        // rejecting the first proposal rejects the chain of dependents and returns their bonds.
+        contract.reject(first);
+        assert_eq!(contract.get_proposal(second).proposal.status, ProposalStatus::Rejected);
+        assert_eq!(contract.get_proposal(third).proposal.status, ProposalStatus::Rejected);
+        assert_eq!(contract.get_queued_proposals_count(), 0);
+        assert_eq!(contract.locked_amount, locked - to_yocto("3"));
+
+        // a proposal whose prerequisite was already rejected is rejected when approved.
+        let fourth = after(&mut contract, Prerequisite::Executed(first));
+        contract.approve(fourth, 0);
+        assert_eq!(contract.get_proposal(fourth).proposal.status, ProposalStatus::Rejected);
+    }
+
+    #[test]
+    fn test_discussion_period() {
+        let mut context = VMContextBuilder::new();
+        let mut policy = VersionedPolicy::Default(vec![accounts(1).into()]).upgrade();
+        let kind: ProposalKind = near_sdk::serde_json::from_value(near_sdk::serde_json::json!({
+            "name": "discussed",
+            "required_instrs": [],
+            "vote_policy": VotePolicy::default(),
+            "discussion_period": "100",
+        }))
+        .unwrap();
+        policy.to_policy_mut().proposal_kinds.push(kind);
+        let mut contract = new_contract(&mut context, policy);
+        let id = create_proposal(&mut context, &mut contract);
+        assert_eq!(contract.get_proposal_phase(id), ProposalPhase::Discussion);
+        contract.counter_propose(
+            id,
+            "counter".to_string(),
+            vec![Instruction::Transfer {
+                token_id: BASE_TOKEN.to_string(),
+                receiver_id: accounts(3).into(),
+                amount: U128(to_yocto("50")),
+            }],
+        );
+
+        testing_env!(context.block_timestamp(100).build());
+        assert_eq!(contract.get_proposal_phase(id), ProposalPhase::Voting);
+        contract.approve(id, 1);
+        assert_eq!(
+            contract.get_proposal(id).proposal.status,
+            ProposalStatus::Approved{ version: 1 }
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_DISCUSSION_PHASE")]
+    fn test_vote_in_discussion_period() {
+        let mut context = VMContextBuilder::new();
+        let mut policy = VersionedPolicy::Default(vec![accounts(1).into()]).upgrade();
+        let kind: ProposalKind = near_sdk::serde_json::from_value(near_sdk::serde_json::json!({
+            "name": "discussed",
+            "required_instrs": [],
+            "vote_policy": VotePolicy::default(),
+            "discussion_period": "100",
+        }))
+        .unwrap();
+        policy.to_policy_mut().proposal_kinds.push(kind);
+        let mut contract = new_contract(&mut context, policy);
+        let id = create_proposal(&mut context, &mut contract);
+        contract.approve(id, 0);
+    }
+
+    fn per_kind_contract(context: &mut VMContextBuilder) -> Contract {
+        let mut policy = VersionedPolicy::Default(vec![accounts(1).into()]).upgrade();
+        let kind: ProposalKind = near_sdk::serde_json::from_value(near_sdk::serde_json::json!({
+            "name": "quick",
+            "required_instrs": [],
+            "vote_policy": VotePolicy::default(),
+            "proposal_period": "50",
+            "proposal_bond": "5",
+            "max_versions": 2,
+        }))
+        .unwrap();
+        policy.to_policy_mut().proposal_kinds.push(kind);
+        new_contract(context, policy)
+    }
+
+    #[test]
+    fn test_per_kind_period_and_bond() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = per_kind_contract(&mut context);
+        testing_env!(context.attached_deposit(5).build());
+        let id = contract.propose("quick".to_string(), transfer_to(accounts(2).into()));
+        assert_eq!(contract.get_proposal(id).proposal.versions[0].bond, U128(5));
+        contract.counter_propose(id, "counter".to_string(), transfer_to(accounts(3).into()));
+
+        testing_env!(context.block_timestamp(50).build());
+        assert_eq!(contract.get_proposal_phase(id), ProposalPhase::Voting);
+        testing_env!(context.block_timestamp(51).build());
+        assert_eq!(contract.get_proposal_phase(id), ProposalPhase::Closed);
+        contract.finalize(id);
+        assert_eq!(contract.get_proposal(id).proposal.status, ProposalStatus::Expired);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_MIN_BOND")]
+    fn test_per_kind_bond_too_low() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = per_kind_contract(&mut context);
+        testing_env!(context.attached_deposit(4).build());
+        contract.propose("quick".to_string(), transfer_to(accounts(2).into()));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TOO_MANY_VERSIONS")]
+    fn test_per_kind_max_versions() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = per_kind_contract(&mut context);
+        testing_env!(context.attached_deposit(5).build());
+        let id = contract.propose("quick".to_string(), transfer_to(accounts(2).into()));
+        contract.counter_propose(id, "counter".to_string(), transfer_to(accounts(3).into()));
+        contract.counter_propose(id, "another".to_string(), transfer_to(accounts(4).into()));
+    }
+
+    #[test]
+    fn test_slash_bonds() {
+        let mut context = VMContextBuilder::new();
+        let mut policy = VersionedPolicy::Default(vec![accounts(1).into()]).upgrade();
+        policy.to_policy_mut().default_vote_policy.weight_kind = WeightKind::TokenWeight;
+        policy.to_policy_mut().roles[1]
+            .permissions
+            .insert("*:RemoveProposal".to_string());
+        let mut contract = new_contract(&mut context, policy);
+        contract.staking_id = Some(accounts(0).into());
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.delegations.insert(&accounts(1).into(), &0);
+        contract.delegate(&accounts(1).into(), U128(100));
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        let locked = contract.locked_amount;
+
+        let id = create_proposal(&mut context, &mut contract);
+        contract.counter_propose(
+            id,
+            "spam".to_string(),
+            vec![Instruction::Transfer {
+                token_id: BASE_TOKEN.to_string(),
+                receiver_id: accounts(1).into(),
+                amount: U128(to_yocto("100")),
+            }],
+        );
+        assert_eq!(contract.locked_amount, locked + to_yocto("2"));
+        contract.veto(id, 1);
+        assert_eq!(contract.get_proposal(id).proposal.versions[1].bond, U128(0));
+        assert_eq!(contract.locked_amount, locked + to_yocto("1"));
+        assert_eq!(contract.get_slashed_amount(), U128(to_yocto("1")));
+        contract.approve(id, 0);
+        assert_eq!(contract.locked_amount, locked);
+
+        let id = create_proposal(&mut context, &mut contract);
+        contract.remove(id);
+        assert_eq!(contract.locked_amount, locked);
+        assert_eq!(contract.get_slashed_amount(), U128(to_yocto("2")));
+    }
+
+    #[test]
+    fn test_execution_outcomes() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = new_contract(
+            &mut context,
+            VersionedPolicy::Default(vec![accounts(1).into()]),
+        );
+        testing_env!(context.attached_deposit(to_yocto("1")).build());
+        let id = contract.propose(
+            "pay twice".to_string(),
+            vec![
+                Instruction::Transfer {
+                    token_id: BASE_TOKEN.to_string(),
+                    receiver_id: accounts(2).into(),
+                    amount: U128(to_yocto("10")),
+                },
+                Instruction::Transfer {
+                    token_id: "token.near".to_string(),
+                    receiver_id: accounts(2).into(),
+                    amount: U128(10),
+                },
+            ],
+        );
+        contract.approve(id, 0);
+        assert_eq!(
+            contract.get_proposal(id).proposal.status,
+            ProposalStatus::Approved{ version: 0 }
+        );
+        resolve_instruction(&mut context, &mut contract, id, 0, true);
+        resolve_instruction(&mut context, &mut contract, id, 1, false);
+        let proposal = contract.get_proposal(id).proposal;
+        assert_eq!(proposal.status, ProposalStatus::Failed{ version: 0 });
+        assert_eq!(proposal.execution[0].outcome, InstructionOutcome::Succeeded);
+        assert_eq!(proposal.execution[1].outcome, InstructionOutcome::Failed);
+
+        // only the failed transfer is run again.
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.retry(id);
+        let proposal = contract.get_proposal(id).proposal;
+        assert_eq!(proposal.status, ProposalStatus::Approved{ version: 0 });
+        assert_eq!(proposal.execution[0].outcome, InstructionOutcome::Succeeded);
+        assert_eq!(proposal.execution[1].outcome, InstructionOutcome::Pending);
+        resolve_instruction(&mut context, &mut contract, id, 1, true);
+        assert_eq!(
+            contract.get_proposal(id).proposal.status,
+            ProposalStatus::Executed{ version: 0 }
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_ENOUGH_GAS")]
+    fn test_execution_needs_gas_for_every_instruction() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .account_balance(to_yocto("1000"))
+            .attached_deposit(to_yocto("1"))
+            .prepaid_gas(300_000_000_000_000)
+            .build());
+        let mut contract = Contract::new(
+            Config::test_config(),
+            VersionedPolicy::Default(vec![accounts(1).into()]),
+        );
+        let call = |method_name: &str| {
+            near_sdk::serde_json::from_value(near_sdk::serde_json::json!({
+                "method_name": method_name,
+                "args": "",
+                "deposit": "0",
+                "gas": "100000000000000",
+            }))
+            .unwrap()
+        };
+        // the calls fit on their own but not along with the callbacks recording their outcome.
+        let id = contract.propose(
+            "calls".to_string(),
+            vec![
+                Instruction::FunctionCall {
+                    receiver_id: accounts(3).into(),
+                    actions: vec![call("first"), call("second")],
+                },
+                Instruction::FunctionCall {
+                    receiver_id: accounts(3).into(),
+                    actions: vec![call("third")],
+                },
+            ],
+        );
+        contract.approve(id, 0);
+    }
+
+    #[test]
+    fn test_atomic_execution() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = new_contract(
+            &mut context,
+            VersionedPolicy::Default(vec![accounts(1).into()]),
+        );
+        let locked = contract.locked_amount;
+        testing_env!(context.attached_deposit(to_yocto("1")).build());
+        let id = contract.propose_with_options(
+            "pay and join".to_string(),
+            vec![
+                Instruction::AddMemberToRole {
+                    member_id: accounts(3).into(),
+                    role: "council".to_string(),
+                },
+                Instruction::Transfer {
+                    token_id: "token.near".to_string(),
+                    receiver_id: accounts(3).into(),
+                    amount: U128(10),
+                },
+            ],
+            ProposalOptions {
+                atomic: true,
+                ..ProposalOptions::default()
+            },
+        );
+        let is_member = |contract: &Contract| match &contract.get_policy().roles[1].kind {
+            RoleKind::Group(members) => members.contains(&accounts(3).to_string()),
+            _ => false,
+        };
+        contract.approve(id, 0);
+        let proposal = contract.get_proposal(id).proposal;
+        assert_eq!(proposal.execution[0].outcome, InstructionOutcome::Staged);
+        assert!(!is_member(&contract));
+        assert_eq!(contract.locked_amount, locked + to_yocto("1"));
+
+        // the failed transfer reverts the staged change and the bond is returned.
+        resolve_instruction(&mut context, &mut contract, id, 1, false);
+        let proposal = contract.get_proposal(id).proposal;
+        assert_eq!(proposal.status, ProposalStatus::Failed{ version: 0 });
+        assert_eq!(proposal.execution[0].outcome, InstructionOutcome::Reverted);
+        assert!(!is_member(&contract));
+        assert_eq!(contract.locked_amount, locked);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.retry(id);
+        assert_eq!(
+            contract.get_proposal(id).proposal.execution[0].outcome,
+            InstructionOutcome::Staged
+        );
+        resolve_instruction(&mut context, &mut contract, id, 1, true);
+        let proposal = contract.get_proposal(id).proposal;
+        assert_eq!(proposal.status, ProposalStatus::Executed{ version: 0 });
+        assert_eq!(proposal.execution[0].outcome, InstructionOutcome::Succeeded);
+        assert!(is_member(&contract));
+        assert_eq!(contract.locked_amount, locked);
+    }
+
+    #[test]
+    fn test_atomic_execution_revalidates_staged() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .account_balance(to_yocto("1000"))
+            .attached_deposit(to_yocto("1"))
+            .build());
+        let mut policy = VersionedPolicy::Default(vec![accounts(1).into()]).upgrade();
+        policy.to_policy_mut().roles.push(RolePermission {
+            name: "board".to_string(),
+            kind: RoleKind::Group(std::collections::HashSet::default()),
+            permissions: std::collections::HashSet::default(),
+        });
+        let mut contract = Contract::new(Config::test_config(), policy);
+        let id = contract.propose_with_options(
+            "join".to_string(),
+            vec![
+                Instruction::AddMemberToRole {
+                    member_id: accounts(3).into(),
+                    role: "council".to_string(),
+                },
+                Instruction::AddMemberToRole {
+                    member_id: accounts(3).into(),
+                    role: "board".to_string(),
+                },
+                Instruction::Transfer {
+                    token_id: "token.near".to_string(),
+                    receiver_id: accounts(3).into(),
+                    amount: U128(10),
+                },
+            ],
+            ProposalOptions {
+                atomic: true,
+                ..ProposalOptions::default()
+            },
+        );
+        contract.approve(id, 0);
+        // the board is dropped from the policy while the transfer is pending.
+        let mut policy = contract.get_policy();
+        policy.roles.retain(|role| role.name != "board");
+        contract.policy.set(&VersionedPolicy::Current(policy));
+
+        // the staged instruction that no longer applies fails without panicking, and
+        // the other one is reverted with it.
+        resolve_instruction(&mut context, &mut contract, id, 2, true);
+        let proposal = contract.get_proposal(id).proposal;
+        assert_eq!(proposal.status, ProposalStatus::Failed{ version: 0 });
+        assert_eq!(proposal.execution[0].outcome, InstructionOutcome::Reverted);
+        assert_eq!(proposal.execution[1].outcome, InstructionOutcome::Failed);
+        assert_eq!(proposal.execution[2].outcome, InstructionOutcome::Succeeded);
+        assert!(matches!(
+            &contract.get_policy().roles[1].kind,
+            RoleKind::Group(members) if !members.contains(&accounts(3).to_string())
+        ));
+    }
+
+    #[test]
+    fn test_move_to_hub() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = new_contract(
+            &mut context,
+            VersionedPolicy::Default(vec![accounts(1).into()]),
+        );
+        let locked = contract.locked_amount;
+        let id = create_proposal(&mut context, &mut contract);
+        contract.move_to_hub(id, accounts(4));
+        assert_eq!(contract.get_proposal(id).proposal.status, ProposalStatus::Moved);
+        assert_eq!(contract.locked_amount, locked);
+
+        // the hub refuses the proposal, which is back in progress.
+        let refused = vec![PromiseResult::Failed];
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            refused
+        );
+        contract.on_proposal_moved(id, accounts(4).into());
+        assert_eq!(contract.get_proposal(id).proposal.status, ProposalStatus::InProgress);
+        assert_eq!(contract.locked_amount, locked + to_yocto("1"));
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.move_to_hub(id, accounts(4));
+        let accepted = vec![PromiseResult::Successful(b"7".to_vec())];
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            accepted
+        );
+        contract.on_proposal_moved(id, accounts(4).into());
+        let proposal = contract.get_proposal(id).proposal;
+        assert_eq!(proposal.status, ProposalStatus::Moved);
+        assert_eq!(
+            proposal.moved_to,
+            Some(ProposalLink { dao_id: accounts(4).into(), proposal_id: 7 })
+        );
+        assert_eq!(proposal.versions[0].bond, U128(0));
+        assert_eq!(contract.locked_amount, locked);
+    }
+
+    #[test]
+    fn test_move_conviction_proposal() {
+        let mut context = VMContextBuilder::new();
+        let mut policy = VersionedPolicy::Default(vec![accounts(1).into()]).upgrade();
+        policy.to_policy_mut().default_vote_policy.conviction = Some(ConvictionPolicy {
+            half_life: WrappedDuration::from(100),
+            min_threshold: WeightOrRatio::Weight(U128(50)),
+            spending_limit: WeightOrRatio::Ratio(1, 2),
+        });
+        let mut contract = new_contract(&mut context, policy);
+        contract.staking_id = Some(accounts(5).into());
+        contract.delegations.insert(&accounts(1).into(), &100);
+        contract.total_delegation_amount = 100;
+        let id = create_proposal(&mut context, &mut contract);
+
+        // withdrawing while the move is pending releases the stake.
+        contract.stake_conviction(id, 0, U128(100));
+        contract.move_to_hub(id, accounts(4));
+        contract.withdraw_conviction(id);
+        assert_eq!(contract.get_conviction_staked(&accounts(1).into()), 0);
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_proposal_moved(id, accounts(4).into());
+        assert_eq!(contract.get_proposal_conviction(id)[0].conviction, U128(0));
+
+        // once moved, the stake is released and withdrawing only clears it.
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.stake_conviction(id, 0, U128(100));
+        contract.move_to_hub(id, accounts(4));
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(b"7".to_vec())]
+        );
+        contract.on_proposal_moved(id, accounts(4).into());
+        assert_eq!(contract.get_conviction_staked(&accounts(1).into()), 0);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.withdraw_conviction(id);
+        assert_eq!(contract.get_conviction_staked(&accounts(1).into()), 0);
+
+        testing_env!(context.predecessor_account_id(accounts(5)).build());
+        contract.undelegate(&accounts(1).into(), U128(100));
+        assert_eq!(contract.get_user_weight(&accounts(1).into()), 0);
+    }
+
+    fn hub_contract(context: &mut VMContextBuilder) -> Contract {
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        let mut policy = VersionedPolicy::Default(vec![accounts(1).into()]).upgrade();
+        policy.to_policy_mut().sub_daos.push(accounts(4).into());
+        Contract::new(Config::test_config(), policy)
+    }
+
+    fn moved_version(receiver_id: AccountId) -> ProposalVersion {
+        ProposalVersion {
+                proposer: accounts(2).into(),
+                description: "escalated".to_string(),
+                instructions: vec![Instruction::Transfer {
+                    token_id: BASE_TOKEN.to_string(),
+                    receiver_id,
+                    amount: U128(to_yocto("100")),
+                }],
+                voted_amount: None,
+                execute_after: None,
+                bond: U128(to_yocto("1")),
+                atomic: false,
+        }
+    }
+
+    #[test]
+    fn test_accept_moved_proposal() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = hub_contract(&mut context);
+        let locked = contract.locked_amount;
+        testing_env!(context.attached_deposit(to_yocto("2")).build());
+        let id = contract.accept_moved_proposal(
+            3,
+            vec![moved_version(accounts(2).into()), moved_version(accounts(3).into())],
+        );
+        let proposal = contract.get_proposal(id).proposal;
+        assert_eq!(proposal.versions.len(), 2);
+        assert_eq!(proposal.approve_count, vec![0, 0]);
+        assert_eq!(
+            proposal.moved_from,
+            Some(ProposalLink { dao_id: accounts(4).into(), proposal_id: 3 })
+        );
+        assert_eq!(contract.locked_amount, locked + to_yocto("2"));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_SUB_DAO")]
+    fn test_accept_moved_proposal_from_unlisted_dao() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = hub_contract(&mut context);
+        testing_env!(context
+            .predecessor_account_id(accounts(5))
+            .attached_deposit(to_yocto("1"))
+            .build());
+        contract.accept_moved_proposal(3, vec![moved_version(accounts(2).into())]);
+    }
+
+    #[test]
+    fn test_payout_keeps_locked_funds() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        let mut contract = Contract::new(
+            Config::test_config(),
+            VersionedPolicy::Default(vec![accounts(1).into()]),
+        );
+        // the whole balance of 100 NEAR includes the bond and storage.
+        let id = create_proposal(&mut context, &mut contract);
+        contract.approve(id, 0);
+        let proposal = contract.get_proposal(id).proposal;
+        assert_eq!(proposal.execution[0].outcome, InstructionOutcome::Failed);
+
+        let id = contract.propose(
+            "call".to_string(),
+            vec![Instruction::FunctionCall {
+                receiver_id: accounts(3).into(),
+                actions: vec![near_sdk::serde_json::from_value(near_sdk::serde_json::json!({
+                    "method_name": "deposit",
+                    "args": "",
+                    "deposit": to_yocto("100").to_string(),
+                    "gas": "10000000000000",
+                }))
+                .unwrap()],
+            }],
+        );
+        contract.approve(id, 0);
+        let proposal = contract.get_proposal(id).proposal;
+        assert_eq!(proposal.execution[0].outcome, InstructionOutcome::Failed);
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::{self, Value};
+use near_sdk::{base64, near_bindgen};
+
+use crate::*;
+
+/// Named proposal with placeholders, so that members only fill in the parameters.
+/// Placeholders are written `{{param}}` in any string of the description or instructions.
+/// Parameters can be strings, numbers or booleans. A string of the instructions that is
+/// only a placeholder takes the parameter with its type, other placeholders take its text.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProposalTemplate {
+    /// Name to propose the template by.
+    pub name: String,
+    /// Proposal kind the built instructions must match, so permissions follow the template.
+    pub kind: String,
+    /// Description of the proposals.
+    pub description: String,
+    /// Instructions as JSON. The args of function calls can be given as JSON, which
+    /// gets encoded in base64 once the placeholders are filled in.
+    pub instructions: String,
+    /// Parameters that must be given to the template.
+    pub params: Vec<String>,
+}
+
+impl ProposalTemplate {
+    /// Asserts that the template is well formed for the given policy: its instructions
+    /// are a JSON array, its kind exists and its placeholders are all parameters.
+    pub fn assert_valid(&self, policy: &Policy) {
+        let instructions: Value =
+            serde_json::from_str(&self.instructions).expect("ERR_INVALID_TEMPLATE");
+        assert!(instructions.is_array(), "ERR_INVALID_TEMPLATE");
+        assert!(
+            policy.proposal_kinds.iter().any(|kind| kind.name == self.kind),
+            "ERR_NO_TEMPLATE_KIND"
+        );
+        for (i, name) in self.params.iter().enumerate() {
+            assert!(!self.params[..i].contains(name), "ERR_DUPLICATE_TEMPLATE_PARAM");
+        }
+        let mut texts = vec![self.description.clone()];
+        collect_strings(&instructions, &mut texts);
+        for text in texts.iter() {
+            for name in placeholders(text) {
+                assert!(self.params.iter().any(|p| p == name), "ERR_UNKNOWN_TEMPLATE_PARAM");
+            }
+        }
+    }
+
+    /// Returns the description and instructions of a proposal with the given parameters.
+    pub fn build(&self, params: &HashMap<String, Value>) -> (String, Vec<Instruction>) {
+        for name in self.params.iter() {
+            assert!(params.contains_key(name), "ERR_MISSING_TEMPLATE_PARAM");
+        }
+        for (name, value) in params.iter() {
+            assert!(self.params.contains(name), "ERR_UNKNOWN_TEMPLATE_PARAM");
+            assert!(
+                value.is_string() || value.is_number() || value.is_boolean(),
+                "ERR_INVALID_TEMPLATE_PARAM"
+            );
+        }
+        let mut instructions: Value =
+            serde_json::from_str(&self.instructions).expect("ERR_INVALID_TEMPLATE");
+        fill_value(&mut instructions, params);
+        encode_args(&mut instructions);
+        let instructions = serde_json::from_value(instructions).expect("ERR_INVALID_TEMPLATE_PARAMS");
+        (fill(&self.description, params), instructions)
+    }
+}
+
+/// Returns the names of the placeholders in the given text.
+fn placeholders(text: &str) -> Vec<&str> {
+    let mut names = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        rest = &rest[start + 2..];
+        match rest.find("}}") {
+            Some(end) => {
+                names.push(&rest[..end]);
+                rest = &rest[end + 2..];
+            }
+            None => break,
+        }
+    }
+    names
+}
+
+fn collect_strings(value: &Value, texts: &mut Vec<String>) {
+    match value {
+        Value::String(text) => texts.push(text.clone()),
+        Value::Array(values) => values.iter().for_each(|v| collect_strings(v, texts)),
+        Value::Object(values) => values.values().for_each(|v| collect_strings(v, texts)),
+        _ => {}
+    }
+}
+
+/// Replaces the placeholders of the parameters in the given text.
+fn fill(text: &str, params: &HashMap<String, Value>) -> String {
+    let mut text = text.to_string();
+    for (name, value) in params.iter() {
+        let value = match value {
+            Value::String(value) => value.clone(),
+            value => value.to_string(),
+        };
+        text = text.replace(&format!("{{{{{}}}}}", name), &value);
+    }
+    text
+}
+
+fn fill_value(value: &mut Value, params: &HashMap<String, Value>) {
+    match value {
+        Value::String(text) => {
+            let param = text
+                .strip_prefix("{{")
+                .and_then(|name| name.strip_suffix("}}"))
+                .and_then(|name| params.get(name));
+            match param {
+                Some(param) => *value = param.clone(),
+                None => *text = fill(text, params),
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(|v| fill_value(v, params)),
+        Value::Object(values) => values.values_mut().for_each(|v| fill_value(v, params)),
+        _ => {}
+    }
+}
+
+/// Encodes the args of function calls given as JSON in base64.
+fn encode_args(instructions: &mut Value) {
+    for instruction in instructions.as_array_mut().into_iter().flatten() {
+        let actions = instruction
+            .pointer_mut("/FunctionCall/actions")
+            .and_then(Value::as_array_mut);
+        for action in actions.into_iter().flatten() {
+            if let Some(args) = action.get_mut("args") {
+                if !args.is_string() {
+                    *args = Value::String(base64::encode(args.to_string()));
+                }
+            }
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Add proposal built from a template of the policy with the given parameters.
+    #[payable]
+    pub fn propose_from_template(&mut self, name: String, params: HashMap<String, Value>) -> u64 {
+        let policy = self.policy.get().unwrap().to_policy();
+        let template = policy.get_template(&name).expect("ERR_NO_TEMPLATE");
+        let (description, instructions) = template.build(&params);
+        assert_eq!(
+            policy.match_proposal_kind(&instructions),
+            template.kind,
+            "ERR_TEMPLATE_KIND_MISMATCH"
+        );
+        self.propose(description, instructions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, MockedBlockchain};
+    use near_sdk_sim::to_yocto;
+
+    use crate::test_utils::*;
+
+    use super::*;
+
+    fn template_contract(context: &mut VMContextBuilder) -> Contract {
+        let mut policy = VersionedPolicy::Default(vec![accounts(1).into()]).upgrade();
+        let kind: ProposalKind = near_sdk::serde_json::from_value(near_sdk::serde_json::json!({
+            "name": "payment",
+            "required_instrs": [4],
+            "vote_policy": VotePolicy::default(),
+        }))
+        .unwrap();
+        policy.to_policy_mut().proposal_kinds.push(kind);
+        policy.to_policy_mut().templates.push(ProposalTemplate {
+            name: "monthly contributor payment".to_string(),
+            kind: "payment".to_string(),
+            description: "Monthly payment to {{receiver}} for {{months}} months".to_string(),
+            instructions: near_sdk::serde_json::json!([{
+                "FunctionCall": {
+                    "receiver_id": "token.near",
+                    "actions": [{
+                        "method_name": "ft_transfer",
+                        "args": {
+                            "receiver_id": "{{receiver}}",
+                            "amount": "{{amount}}",
+                            "months": "{{months}}",
+                        },
+                        "deposit": "1",
+                        "gas": "10000000000000",
+                    }],
+                },
+            }])
+            .to_string(),
+            params: vec!["receiver".to_string(), "amount".to_string(), "months".to_string()],
+        });
+        new_contract(context, policy)
+    }
+
+    #[test]
+    fn test_propose_from_template() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = template_contract(&mut context);
+        testing_env!(context.attached_deposit(to_yocto("1")).build());
+        let params: HashMap<String, near_sdk::serde_json::Value> = vec![
+            ("receiver".to_string(), "bob".into()),
+            ("amount".to_string(), "100".into()),
+            ("months".to_string(), 3.into()),
+        ]
+        .into_iter()
+        .collect();
+        let id = contract.propose_from_template("monthly contributor payment".to_string(), params);
+        let proposal = contract.get_proposal(id).proposal;
+        assert_eq!(proposal.kind, "payment");
+        assert_eq!(proposal.versions[0].description, "Monthly payment to bob for 3 months");
+        let instructions = near_sdk::serde_json::to_value(&proposal.versions[0].instructions).unwrap();
+        let args = instructions[0]["FunctionCall"]["actions"][0]["args"].as_str().unwrap();
+        let args: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_slice(&near_sdk::base64::decode(args).unwrap()).unwrap();
+        assert_eq!(
+            args,
+            near_sdk::serde_json::json!({ "receiver_id": "bob", "amount": "100", "months": 3 })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_MISSING_TEMPLATE_PARAM")]
+    fn test_propose_from_template_missing_param() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = template_contract(&mut context);
+        testing_env!(context.attached_deposit(to_yocto("1")).build());
+        let params: HashMap<String, near_sdk::serde_json::Value> =
+            vec![("receiver".to_string(), "bob".into())].into_iter().collect();
+        contract.propose_from_template("monthly contributor payment".to_string(), params);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_UNKNOWN_TEMPLATE_PARAM")]
+    fn test_change_policy_invalid_template() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = template_contract(&mut context);
+        let mut policy = contract.get_policy();
+        policy.templates[0].description = "Payment to {{recipient}}".to_string();
+        testing_env!(context.attached_deposit(to_yocto("1")).build());
+        contract.propose(
+            "change policy".to_string(),
+            vec![Instruction::ChangePolicy { policy: VersionedPolicy::Current(policy) }],
+        );
+    }
+}
@@ -0,0 +1,61 @@
+use near_sdk::json_types::U128;
+use near_sdk::test_utils::{accounts, VMContextBuilder};
+use near_sdk::{testing_env, AccountId, MockedBlockchain, PromiseResult};
+use near_sdk_sim::to_yocto;
+
+use crate::types::BASE_TOKEN;
+use crate::*;
+
+/// Creates the contract as accounts(1) with a balance of 1000 NEAR and the given policy.
+pub(crate) fn new_contract(context: &mut VMContextBuilder, policy: VersionedPolicy) -> Contract {
+    testing_env!(context
+        .predecessor_account_id(accounts(1))
+        .account_balance(to_yocto("1000"))
+        .build());
+    Contract::new(Config::test_config(), policy)
+}
+
+/// Proposes a transfer of 100 NEAR to accounts(2) with a bond of 1 NEAR.
+pub(crate) fn create_proposal(context: &mut VMContextBuilder, contract: &mut Contract) -> u64 {
+    testing_env!(context.attached_deposit(to_yocto("1")).build());
+    contract.propose(
+        "test".to_string(),
+        vec![Instruction::Transfer {
+            token_id: BASE_TOKEN.to_string(),
+            receiver_id: accounts(2).into(),
+            amount: U128(to_yocto("100")),
+        }],
+    )
+}
+
+/// Returns the instructions to transfer 10 NEAR to the given account.
+pub(crate) fn transfer_to(account_id: AccountId) -> Vec<Instruction> {
+    vec![Instruction::Transfer {
+        token_id: BASE_TOKEN.to_string(),
+        receiver_id: account_id,
+        amount: U128(to_yocto("10")),
+    }]
+}
+
+/// Calls back the contract with the result of the promise of the given instruction.
+pub(crate) fn resolve_instruction(
+    context: &mut VMContextBuilder,
+    contract: &mut Contract,
+    id: u64,
+    index: u64,
+    success: bool,
+) {
+    let result = if success {
+        PromiseResult::Successful(vec![])
+    } else {
+        PromiseResult::Failed
+    };
+    testing_env!(
+        context.predecessor_account_id(accounts(0)).build(),
+        Default::default(),
+        Default::default(),
+        Default::default(),
+        vec![result]
+    );
+    contract.on_instruction_executed(id, index);
+}
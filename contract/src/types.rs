@@ -2,7 +2,7 @@ use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::env::BLOCKCHAIN_INTERFACE;
 use near_sdk::json_types::Base64VecU8;
 use near_sdk::serde::{Deserialize, Serialize};
-use near_sdk::{env, AccountId, Balance, Gas};
+use near_sdk::{env, AccountId, Balance, Gas, Promise};
 
 const BLOCKCHAIN_INTERFACE_NOT_SET_ERR: &str = "Blockchain interface not set.";
 
@@ -20,6 +20,27 @@ pub const GAS_FOR_UPGRADE_SELF_DEPLOY: Gas = 30_000_000_000_000;
 
 pub const GAS_FOR_UPGRADE_REMOTE_DEPLOY: Gas = 10_000_000_000_000;
 
+/// Gas for recording the outcome of an executed instruction.
+pub const GAS_FOR_EXECUTION_CALLBACK: Gas = 10_000_000_000_000;
+
+/// Gas for another DAO to accept a proposal moved to it.
+pub const GAS_FOR_ACCEPT_MOVED_PROPOSAL: Gas = 30_000_000_000_000;
+
+/// Gas for recording the outcome of moving a proposal.
+pub const GAS_FOR_MOVE_CALLBACK: Gas = 10_000_000_000_000;
+
+/// Gas for recording the outcome of claiming recurring payments and vested tokens.
+pub const GAS_FOR_PAYMENT_CALLBACK: Gas = 10_000_000_000_000;
+
+/// Gas for querying the balance of a token held by this contract.
+pub const GAS_FOR_FT_BALANCE_OF: Gas = 5_000_000_000_000;
+
+/// Gas for checking that the token balance covers a payout and making it.
+pub const GAS_FOR_PAYOUT_CALLBACK: Gas = 20_000_000_000_000;
+
+/// Gas for checking that the token balance covers new reserved funds and reserving them.
+pub const GAS_FOR_RESERVE_CALLBACK: Gas = 10_000_000_000_000;
+
 /// Configuration of the DAO.
 #[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
 #[serde(crate = "near_sdk::serde")]
@@ -72,6 +93,12 @@ pub enum Action {
     Finalize,
     /// Move a proposal to the hub to shift into another DAO.
     MoveToHub,
+    /// Vote to abstain on given proposal.
+    VoteAbstain,
+    /// Cancel an approved proposal while it is queued for execution.
+    CancelProposal,
+    /// Retry the failed instructions of an executed proposal.
+    RetryExecution,
 }
 
 impl Action {
@@ -120,32 +147,8 @@ pub(crate) fn upgrade_self(hash: &[u8]) {
     }
 }
 
-pub(crate) fn upgrade_remote(receiver_id: &AccountId, method_name: &str, hash: &[u8]) {
-    unsafe {
-        BLOCKCHAIN_INTERFACE.with(|b| {
-            // Load input into register 0.
-            b.borrow()
-                .as_ref()
-                .expect(BLOCKCHAIN_INTERFACE_NOT_SET_ERR)
-                .storage_read(hash.len() as _, hash.as_ptr() as _, 0);
-            let promise_id = b
-                .borrow()
-                .as_ref()
-                .expect(BLOCKCHAIN_INTERFACE_NOT_SET_ERR)
-                .promise_batch_create(receiver_id.len() as _, receiver_id.as_ptr() as _);
-            let attached_gas = env::prepaid_gas() - env::used_gas() - GAS_FOR_UPGRADE_REMOTE_DEPLOY;
-            b.borrow()
-                .as_ref()
-                .expect(BLOCKCHAIN_INTERFACE_NOT_SET_ERR)
-                .promise_batch_action_function_call(
-                    promise_id,
-                    method_name.len() as _,
-                    method_name.as_ptr() as _,
-                    u64::MAX as _,
-                    0 as _,
-                    0 as _,
-                    attached_gas,
-                );
-        });
-    }
+/// Upgrades remote contract with the code stored under the given hash, attaching the given gas.
+pub(crate) fn upgrade_remote(receiver_id: &AccountId, method_name: &str, hash: &[u8], gas: Gas) -> Promise {
+    let code = env::storage_read(hash).expect("ERR_NO_BLOB");
+    Promise::new(receiver_id.clone()).function_call(method_name.as_bytes().to_vec(), code, 0, gas)
 }
@@ -0,0 +1,412 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{WrappedDuration, WrappedTimestamp, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_contract_standards::fungible_token::core_impl::ext_fungible_token;
+use near_sdk::{
+    env, is_promise_success, near_bindgen, promise_result_as_success, AccountId, Balance, Promise,
+};
+
+use crate::proposals::{ext_self, ft_transfer};
+use crate::types::{
+    BASE_TOKEN, GAS_FOR_FT_BALANCE_OF, GAS_FOR_PAYMENT_CALLBACK, GAS_FOR_RESERVE_CALLBACK,
+};
+use crate::*;
+
+/// Tokens that vest linearly to the beneficiary over `duration` from `start`.
+/// Nothing can be claimed before the cliff.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, PartialEq)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+#[serde(crate = "near_sdk::serde")]
+pub struct VestingSchedule {
+    /// Token that vests.
+    pub token_id: AccountId,
+    /// Account that claims the vested tokens.
+    pub beneficiary_id: AccountId,
+    /// Amount that is vested at the end of the schedule.
+    pub total: U128,
+    /// Time the tokens start vesting from.
+    pub start: WrappedTimestamp,
+    /// Time from the start until the first tokens can be claimed.
+    pub cliff: WrappedDuration,
+    /// Time from the start until all tokens are vested.
+    pub duration: WrappedDuration,
+}
+
+impl VestingSchedule {
+    pub fn assert_valid(&self) {
+        assert!(self.duration.0 > 0, "ERR_ZERO_DURATION");
+        assert!(self.cliff.0 <= self.duration.0, "ERR_CLIFF_AFTER_END");
+    }
+}
+
+/// Vesting schedule set up by a proposal, with the tokens claimed so far.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct Vesting {
+    #[serde(flatten)]
+    pub schedule: VestingSchedule,
+    /// Amount claimed by the beneficiary.
+    pub claimed: U128,
+    /// Time the schedule was revoked at. Tokens stop vesting from then on.
+    pub revoked_at: Option<WrappedTimestamp>,
+}
+
+impl Vesting {
+    /// Returns the amount vested by the given time, claimed or not.
+    pub fn vested(&self, timestamp: u64) -> Balance {
+        let schedule = &self.schedule;
+        let timestamp = match self.revoked_at {
+            Some(revoked_at) => std::cmp::min(revoked_at.0, timestamp),
+            None => timestamp,
+        };
+        if timestamp < schedule.start.0 + schedule.cliff.0 {
+            return 0;
+        }
+        let elapsed = (timestamp - schedule.start.0) as u128;
+        let duration = schedule.duration.0 as u128;
+        if elapsed >= duration {
+            return schedule.total.0;
+        }
+        // Split the total to avoid overflows.
+        schedule.total.0 / duration * elapsed + schedule.total.0 % duration * elapsed / duration
+    }
+}
+
+impl Contract {
+    /// Returns the amount of the given token reserved for vesting schedules and recurring payments.
+    pub(crate) fn internal_reserved_amount(&self, token_id: &AccountId) -> Balance {
+        self.reserved_amounts.get(token_id).unwrap_or_default()
+    }
+
+    /// Returns the amount of NEAR that can be spent, outside of storage, bonds and reserved funds.
+    pub(crate) fn internal_available_amount(&self) -> Balance {
+        env::account_balance()
+            .saturating_sub(self.locked_amount)
+            .saturating_sub(self.internal_reserved_amount(&BASE_TOKEN.to_string()))
+    }
+
+    /// Whether the amount of NEAR can be spent without touching storage, bonds and reserved funds.
+    pub(crate) fn internal_can_spend(&self, amount: Balance) -> bool {
+        amount <= self.internal_available_amount()
+    }
+
+    pub(crate) fn internal_reserve(&mut self, token_id: &AccountId, amount: Balance) {
+        let reserved = self.internal_reserved_amount(token_id);
+        self.reserved_amounts.insert(token_id, &(reserved + amount));
+    }
+
+    pub(crate) fn internal_unreserve(&mut self, token_id: &AccountId, amount: Balance) {
+        let reserved = self.internal_reserved_amount(token_id);
+        self.reserved_amounts.insert(token_id, &(reserved - amount));
+    }
+
+    /// Checks the balance of the given token before the instruction of the given
+    /// proposal reserves funds of it.
+    pub(crate) fn internal_check_reserve(&self, id: u64, index: u64, token_id: &AccountId) -> Promise {
+        ext_fungible_token::ft_balance_of(env::current_account_id(), token_id, 0, GAS_FOR_FT_BALANCE_OF)
+            .then(ext_self::on_reserve_balance(
+                id,
+                index,
+                &env::current_account_id(),
+                0,
+                GAS_FOR_RESERVE_CALLBACK,
+            ))
+    }
+
+    /// Sets up a vesting schedule, reserving its total, and returns its id.
+    pub(crate) fn internal_create_vesting(&mut self, schedule: &VestingSchedule) -> u64 {
+        let id = self.last_vesting_id;
+        self.internal_reserve(&schedule.token_id, schedule.total.0);
+        self.vestings.insert(
+            &id,
+            &Vesting {
+                schedule: schedule.clone(),
+                claimed: U128(0),
+                revoked_at: None,
+            },
+        );
+        self.last_vesting_id += 1;
+        id
+    }
+
+    /// Stops a vesting schedule and returns the unvested tokens to the treasury.
+    /// The tokens vested already can still be claimed.
+    pub(crate) fn internal_revoke_vesting(&mut self, id: u64) {
+        let mut vesting = self.vestings.get(&id).expect("ERR_NO_VESTING");
+        assert!(vesting.revoked_at.is_none(), "ERR_VESTING_REVOKED");
+        let now = env::block_timestamp();
+        let unvested = vesting.schedule.total.0 - vesting.vested(now);
+        vesting.revoked_at = Some(WrappedTimestamp::from(now));
+        self.internal_unreserve(&vesting.schedule.token_id, unvested);
+        self.vestings.insert(&id, &vesting);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Claim the vested tokens of a vesting schedule. Only the beneficiary can claim.
+    pub fn claim_vested(&mut self, id: u64) -> Promise {
+        let mut vesting = self.vestings.get(&id).expect("ERR_NO_VESTING");
+        assert_eq!(
+            vesting.schedule.beneficiary_id,
+            env::predecessor_account_id(),
+            "ERR_NOT_BENEFICIARY"
+        );
+        let amount = vesting.vested(env::block_timestamp()) - vesting.claimed.0;
+        assert!(amount > 0, "ERR_NOTHING_VESTED");
+        vesting.claimed = U128(vesting.claimed.0 + amount);
+        self.vestings.insert(&id, &vesting);
+        self.internal_unreserve(&vesting.schedule.token_id, amount);
+        self.internal_payout(
+            &vesting.schedule.token_id,
+            &vesting.schedule.beneficiary_id,
+            amount,
+        )
+        .expect("ERR_NOT_ENOUGH_FUNDS")
+        .then(ext_self::on_vested_claimed(
+            id,
+            U128(amount),
+            &env::current_account_id(),
+            0,
+            GAS_FOR_PAYMENT_CALLBACK,
+        ))
+    }
+
+    /// Makes the tokens claimable again if paying them out failed.
+    #[private]
+    pub fn on_vested_claimed(&mut self, id: u64, amount: U128) {
+        if !is_promise_success() {
+            let mut vesting = self.vestings.get(&id).expect("ERR_NO_VESTING");
+            vesting.claimed = U128(vesting.claimed.0 - amount.0);
+            self.vestings.insert(&id, &vesting);
+            self.internal_reserve(&vesting.schedule.token_id, amount.0);
+        }
+    }
+
+    /// Makes a payout of a token once its balance is known to cover it on top of
+    /// the reserved funds.
+    #[private]
+    pub fn on_payout_balance(&mut self, token_id: AccountId, receiver_id: AccountId, amount: U128) -> Promise {
+        let balance = promise_result_as_success()
+            .and_then(|result| near_sdk::serde_json::from_slice::<U128>(&result).ok())
+            .expect("ERR_NO_BALANCE");
+        assert!(
+            amount.0 + self.internal_reserved_amount(&token_id) <= balance.0,
+            "ERR_NOT_ENOUGH_FUNDS"
+        );
+        ft_transfer(&token_id, &receiver_id, amount.0)
+    }
+
+    /// Reserves the funds of a recurring payment or vesting schedule in a fungible token
+    /// once the balance of the token is known to cover them on top of the funds reserved already.
+    #[private]
+    pub fn on_reserve_balance(&mut self, id: u64, index: u64) {
+        let balance = promise_result_as_success()
+            .and_then(|result| near_sdk::serde_json::from_slice::<U128>(&result).ok())
+            .expect("ERR_NO_BALANCE");
+        let proposal: Proposal = self.proposals.get(&id).expect("ERR_NO_PROPOSAL").into();
+        let covered = |contract: &Self, token_id: &AccountId, total: Balance| {
+            assert!(
+                total + contract.internal_reserved_amount(token_id) <= balance.0,
+                "ERR_NOT_ENOUGH_FUNDS"
+            );
+        };
+        match &proposal.execution[index as usize].instruction {
+            Instruction::CreateRecurringPayment { payment } => {
+                let total = payment.total(env::block_timestamp()).expect("ERR_PAYMENT_OVERFLOW");
+                covered(self, &payment.token_id, total);
+                self.internal_create_payment(payment);
+            }
+            Instruction::CreateVesting { vesting } => {
+                covered(self, &vesting.token_id, vesting.total.0);
+                self.internal_create_vesting(vesting);
+            }
+            _ => env::panic(b"ERR_NOTHING_TO_RESERVE"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, MockedBlockchain, PromiseResult};
+    use near_sdk_sim::to_yocto;
+
+    use crate::proposals::{InstructionOutcome, ProposalStatus};
+    use crate::test_utils::*;
+
+    use super::*;
+
+    fn vesting_contract(context: &mut VMContextBuilder) -> Contract {
+        let mut contract = new_contract(
+            context,
+            VersionedPolicy::Default(vec![accounts(1).into()]),
+        );
+        testing_env!(context.attached_deposit(to_yocto("1")).build());
+        let id = contract.propose(
+            "vesting".to_string(),
+            vec![Instruction::CreateVesting {
+                vesting: VestingSchedule {
+                    token_id: BASE_TOKEN.to_string(),
+                    beneficiary_id: accounts(2).into(),
+                    total: U128(to_yocto("900")),
+                    start: WrappedTimestamp::from(0),
+                    cliff: WrappedDuration::from(10),
+                    duration: WrappedDuration::from(100),
+                },
+            }],
+        );
+        contract.approve(id, 0);
+        contract
+    }
+
+    #[test]
+    fn test_vesting() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = vesting_contract(&mut context);
+        let reserved = |contract: &Contract| contract.get_reserved_amount(BASE_TOKEN.to_string()).0;
+        assert_eq!(reserved(&contract), to_yocto("900"));
+
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .block_timestamp(5)
+            .build());
+        assert_eq!(contract.get_vesting(0).claimable.0, 0);
+        testing_env!(context.block_timestamp(40).build());
+        assert_eq!(contract.get_vesting(0).claimable.0, to_yocto("360"));
+        contract.claim_vested(0);
+        assert_eq!(contract.get_vesting(0).vesting.claimed.0, to_yocto("360"));
+        assert_eq!(reserved(&contract), to_yocto("540"));
+
+        // failed payout makes the tokens claimable again.
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_vested_claimed(0, U128(to_yocto("360")));
+        assert_eq!(contract.get_vesting(0).vesting.claimed.0, 0);
+        assert_eq!(reserved(&contract), to_yocto("900"));
+
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.claim_vested(0);
+
+        // revoking returns the unvested tokens, the vested ones can still be claimed.
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .block_timestamp(50)
+            .build());
+        let id = contract.propose(
+            "revoke vesting".to_string(),
+            vec![Instruction::RevokeVesting { vesting_id: 0 }],
+        );
+        contract.approve(id, 0);
+        assert_eq!(reserved(&contract), to_yocto("90"));
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .block_timestamp(200)
+            .build());
+        assert_eq!(contract.get_vestings(0, 10)[0].claimable.0, to_yocto("90"));
+        contract.claim_vested(0);
+        assert_eq!(reserved(&contract), 0);
+    }
+
+    #[test]
+    fn test_ft_vesting() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = vesting_contract(&mut context);
+        let vesting = VestingSchedule {
+            token_id: accounts(4).into(),
+            beneficiary_id: accounts(2).into(),
+            total: U128(to_yocto("10")),
+            start: WrappedTimestamp::from(0),
+            cliff: WrappedDuration::from(10),
+            duration: WrappedDuration::from(100),
+        };
+        let id = contract.propose(
+            "vesting".to_string(),
+            vec![Instruction::CreateVesting { vesting }],
+        );
+        contract.approve(id, 0);
+        let proposal = contract.get_proposal(id).proposal;
+        assert_eq!(proposal.execution[0].outcome, InstructionOutcome::Pending);
+        assert_eq!(contract.get_last_vesting_id(), 1);
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(
+                format!("\"{}\"", to_yocto("10")).into_bytes()
+            )]
+        );
+        contract.on_reserve_balance(id, 0);
+        assert_eq!(contract.get_last_vesting_id(), 2);
+        assert_eq!(contract.get_reserved_amount(accounts(4).into()).0, to_yocto("10"));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_ENOUGH_FUNDS")]
+    fn test_ft_vesting_not_covered() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = vesting_contract(&mut context);
+        let id = contract.propose(
+            "vesting".to_string(),
+            vec![Instruction::CreateVesting {
+                vesting: VestingSchedule {
+                    token_id: accounts(4).into(),
+                    beneficiary_id: accounts(2).into(),
+                    total: U128(to_yocto("10")),
+                    start: WrappedTimestamp::from(0),
+                    cliff: WrappedDuration::from(10),
+                    duration: WrappedDuration::from(100),
+                },
+            }],
+        );
+        contract.approve(id, 0);
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(
+                format!("\"{}\"", to_yocto("9")).into_bytes()
+            )]
+        );
+        contract.on_reserve_balance(id, 0);
+    }
+
+    #[test]
+    fn test_vesting_reserves_funds() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = vesting_contract(&mut context);
+        assert!(contract.get_available_amount().0 < to_yocto("100"));
+        let id = contract.propose(
+            "spend reserved".to_string(),
+            vec![Instruction::Transfer {
+                token_id: BASE_TOKEN.to_string(),
+                receiver_id: accounts(3).into(),
+                amount: U128(to_yocto("200")),
+            }],
+        );
+        contract.approve(id, 0);
+        let proposal = contract.get_proposal(id).proposal;
+        assert_eq!(proposal.status, ProposalStatus::Failed{ version: 0 });
+        assert_eq!(proposal.execution[0].outcome, InstructionOutcome::Failed);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOTHING_VESTED")]
+    fn test_claim_before_cliff() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = vesting_contract(&mut context);
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .block_timestamp(5)
+            .build());
+        contract.claim_vested(0);
+    }
+}
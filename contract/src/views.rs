@@ -1,5 +1,7 @@
 use std::cmp::min;
 
+use near_sdk::json_types::WrappedTimestamp;
+
 use crate::*;
 
 /// This is format of output via JSON for the proposal.
@@ -12,6 +14,18 @@ pub struct ProposalOutput {
     pub proposal: Proposal,
 }
 
+/// This is format of output via JSON for the vesting schedule.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VestingOutput {
+    /// Id of the vesting schedule.
+    pub id: u64,
+    #[serde(flatten)]
+    pub vesting: Vesting,
+    /// Amount that is vested and not claimed yet.
+    pub claimable: U128,
+}
+
 /// This is format of output via JSON for the bounty.
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
@@ -22,6 +36,18 @@ pub struct BountyOutput {
     pub bounty: Bounty,
 }
 
+/// This is format of output via JSON for the recurring payment.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PaymentOutput {
+    /// Id of the recurring payment.
+    pub id: u64,
+    #[serde(flatten)]
+    pub payment: PaymentStream,
+    /// Number of payments that are due and not claimed yet.
+    pub claimable: u32,
+}
+
 #[near_bindgen]
 impl Contract {
     /// Returns semver of this contract.
@@ -49,9 +75,21 @@ impl Contract {
         env::storage_read(&CryptoHash::from(hash)).is_some()
     }
 
-    /// Returns available amount of NEAR that can be spent (outside of amount for storage and bonds).
+    /// Returns available amount of NEAR that can be spent (outside of amount for storage, bonds
+    /// and funds reserved for vesting).
     pub fn get_available_amount(&self) -> U128 {
-        U128(env::account_balance() - self.locked_amount)
+        U128(self.internal_available_amount())
+    }
+
+    /// Returns the amount of the given token reserved for vesting and recurring payments
+    /// and not claimed yet.
+    pub fn get_reserved_amount(&self, token_id: AccountId) -> U128 {
+        U128(self.internal_reserved_amount(&token_id))
+    }
+
+    /// Returns the amount of NEAR kept from the bonds of vetoed and removed proposals.
+    pub fn get_slashed_amount(&self) -> U128 {
+        U128(self.slashed_amount)
     }
 
     /// Returns total delegated stake.
@@ -68,6 +106,39 @@ impl Contract {
         )
     }
 
+    /// Returns the current voting phase of the given proposal.
+    pub fn get_proposal_phase(&self, id: u64) -> ProposalPhase {
+        let proposal: Proposal = self.proposals.get(&id).expect("ERR_NO_PROPOSAL").into();
+        self.policy.get().unwrap().to_policy().proposal_phase(&proposal)
+    }
+
+    /// Returns the current vote escrow lock of the given account.
+    pub fn get_vote_lock(&self, account_id: ValidAccountId) -> Option<VoteLock> {
+        self.get_vote_lock_at(account_id.as_ref(), env::block_timestamp())
+    }
+
+    /// Returns the member the given account delegated their vote to.
+    pub fn get_vote_delegation(&self, account_id: ValidAccountId) -> Option<VoteDelegation> {
+        self.vote_delegations.get(account_id.as_ref())
+    }
+
+    /// Returns the members that delegated their vote to the given account.
+    pub fn get_vote_delegators(&self, account_id: ValidAccountId) -> Vec<AccountId> {
+        self.vote_delegators
+            .get(account_id.as_ref())
+            .unwrap_or_default()
+    }
+
+    /// Returns total delegated stake just before the given time.
+    pub fn delegation_total_supply_at(&self, timestamp: WrappedTimestamp) -> U128 {
+        U128(self.get_total_delegation_at(timestamp.0))
+    }
+
+    /// Returns delegated stake to given account just before the given time.
+    pub fn delegation_balance_at(&self, account_id: ValidAccountId, timestamp: WrappedTimestamp) -> U128 {
+        U128(self.get_user_weight_at(account_id.as_ref(), timestamp.0))
+    }
+
     /// Last proposal's id.
     pub fn get_last_proposal_id(&self) -> u64 {
         self.last_proposal_id
@@ -85,6 +156,26 @@ impl Contract {
             .collect()
     }
 
+    /// Get number of proposals queued for execution.
+    pub fn get_queued_proposals_count(&self) -> u64 {
+        self.queued_proposals.len()
+    }
+
+    /// Get the proposals queued for execution in paginated view. Indices are
+    /// positions in the queue, which is reordered as proposals leave it.
+    pub fn get_queued_proposals(&self, from_index: u64, limit: u64) -> Vec<ProposalOutput> {
+        let ids = self.queued_proposals.as_vector();
+        (from_index..min(ids.len(), from_index + limit))
+            .map(|index| {
+                let id = ids.get(index).unwrap();
+                ProposalOutput {
+                    id,
+                    proposal: self.proposals.get(&id).expect("ERR_NO_PROPOSAL").into(),
+                }
+            })
+            .collect()
+    }
+
     /// Get specific proposal.
     pub fn get_proposal(&self, id: u64) -> ProposalOutput {
         let proposal = self.proposals.get(&id).expect("ERR_NO_PROPOSAL");
@@ -131,4 +222,60 @@ impl Contract {
     pub fn get_bounty_number_of_claims(&self, id: u64) -> u32 {
         self.bounty_claims_count.get(&id).unwrap_or_default()
     }
+
+    /// Get given recurring payment by id.
+    pub fn get_payment(&self, id: u64) -> PaymentOutput {
+        let payment = self.payments.get(&id).expect("ERR_NO_PAYMENT");
+        PaymentOutput {
+            id,
+            claimable: payment.due(env::block_timestamp()) - payment.claimed,
+            payment,
+        }
+    }
+
+    /// Get number of recurring payments.
+    pub fn get_last_payment_id(&self) -> u64 {
+        self.last_payment_id
+    }
+
+    /// Get `limit` of recurring payments from given index.
+    pub fn get_payments(&self, from_index: u64, limit: u64) -> Vec<PaymentOutput> {
+        (from_index..std::cmp::min(from_index + limit, self.last_payment_id))
+            .filter_map(|id| {
+                self.payments.get(&id).map(|payment| PaymentOutput {
+                    id,
+                    claimable: payment.due(env::block_timestamp()) - payment.claimed,
+                    payment,
+                })
+            })
+            .collect()
+    }
+
+    /// Get given vesting schedule by id.
+    pub fn get_vesting(&self, id: u64) -> VestingOutput {
+        let vesting = self.vestings.get(&id).expect("ERR_NO_VESTING");
+        VestingOutput {
+            id,
+            claimable: U128(vesting.vested(env::block_timestamp()) - vesting.claimed.0),
+            vesting,
+        }
+    }
+
+    /// Get number of vesting schedules.
+    pub fn get_last_vesting_id(&self) -> u64 {
+        self.last_vesting_id
+    }
+
+    /// Get `limit` of vesting schedules from given index.
+    pub fn get_vestings(&self, from_index: u64, limit: u64) -> Vec<VestingOutput> {
+        (from_index..std::cmp::min(from_index + limit, self.last_vesting_id))
+            .filter_map(|id| {
+                self.vestings.get(&id).map(|vesting| VestingOutput {
+                    id,
+                    claimable: U128(vesting.vested(env::block_timestamp()) - vesting.claimed.0),
+                    vesting,
+                })
+            })
+            .collect()
+    }
 }
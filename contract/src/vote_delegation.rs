@@ -0,0 +1,275 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::ValidAccountId;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId, Promise, StorageUsage};
+
+use crate::policy::UserInfo;
+use crate::proposals::{DelegatedWeight, Proposal};
+use crate::types::Action;
+use crate::*;
+
+/// Most members that can delegate their vote to the same delegate.
+pub const MAX_VOTE_DELEGATORS: usize = 100;
+
+/// Delegation of a member's vote to another member.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VoteDelegation {
+    /// Member that votes on behalf of the delegator.
+    pub delegate_id: AccountId,
+    /// Proposal kinds the delegation applies to. Applies to all kinds if not set.
+    pub kinds: Option<Vec<String>>,
+}
+
+impl VoteDelegation {
+    pub fn applies_to(&self, kind: &String) -> bool {
+        match &self.kinds {
+            Some(kinds) => kinds.contains(kind),
+            None => true,
+        }
+    }
+}
+
+impl Contract {
+    /// Returns the weights the members who delegated to `delegate_id` lend to their
+    /// vote on the given proposal. Members that already voted or that can't take
+    /// the action themselves are left out. Delegations are not followed further,
+    /// so a delegate only carries the weight of their direct delegators.
+    pub(crate) fn internal_delegated_weights(
+        &self,
+        policy: &Policy,
+        proposal: &Proposal,
+        delegate_id: &AccountId,
+        action: &Action,
+    ) -> Vec<DelegatedWeight> {
+        let vote_policy = policy
+            .get_vote_policy(&proposal.kind)
+            .unwrap_or(&policy.default_vote_policy);
+        let mut delegated = vec![];
+        for account_id in self.vote_delegators.get(delegate_id).unwrap_or_default() {
+            let applies = self
+                .vote_delegations
+                .get(&account_id)
+                .map(|delegation| delegation.applies_to(&proposal.kind))
+                .unwrap_or(false);
+            if !applies || proposal.has_voted(&account_id) {
+                continue;
+            }
+            let user = || UserInfo {
+                amount: self.get_user_weight(&account_id),
+                account_id: account_id.clone(),
+            };
+            if !policy.can_execute_action(user(), &proposal.kind, action) {
+                continue;
+            }
+            let houses = policy.user_houses(vote_policy, user());
+            let weight = vote_policy.vote_weight(self.internal_vote_power(
+                policy,
+                vote_policy,
+                &account_id,
+                proposal.submission_time.0,
+            ));
+            delegated.push(DelegatedWeight { account_id, weight, houses });
+        }
+        delegated
+    }
+
+    /// Removes the delegation of the given member, if any.
+    fn internal_remove_vote_delegation(&mut self, account_id: &AccountId) {
+        if let Some(delegation) = self.vote_delegations.remove(account_id) {
+            let mut delegators = self
+                .vote_delegators
+                .get(&delegation.delegate_id)
+                .unwrap_or_default();
+            delegators.retain(|delegator| delegator != account_id);
+            if delegators.is_empty() {
+                self.vote_delegators.remove(&delegation.delegate_id);
+            } else {
+                self.vote_delegators
+                    .insert(&delegation.delegate_id, &delegators);
+            }
+        }
+    }
+
+    /// Charges the given member for the storage their call added since `initial_storage`
+    /// out of the attached deposit, or refunds the storage it released.
+    fn internal_settle_storage(&mut self, account_id: &AccountId, initial_storage: StorageUsage) {
+        let storage = env::storage_usage();
+        let refund = if storage >= initial_storage {
+            let storage_cost = (storage - initial_storage) as Balance * env::storage_byte_cost();
+            assert!(
+                env::attached_deposit() >= storage_cost,
+                "ERR_NOT_ENOUGH_DEPOSIT:{}",
+                storage_cost
+            );
+            self.locked_amount += storage_cost;
+            env::attached_deposit() - storage_cost
+        } else {
+            let storage_cost = (initial_storage - storage) as Balance * env::storage_byte_cost();
+            self.locked_amount -= storage_cost;
+            env::attached_deposit() + storage_cost
+        };
+        if refund > 0 {
+            Promise::new(account_id.clone()).transfer(refund);
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Delegate the caller's vote to `delegate_id`, replacing any previous delegation.
+    /// With `kinds` set, the delegation only applies to proposals of those kinds.
+    /// Voting directly on a proposal overrides the delegation for that proposal.
+    /// The caller must be able to vote themselves, and pays for the storage of the
+    /// delegation out of the attached deposit.
+    #[payable]
+    pub fn delegate_vote(&mut self, delegate_id: ValidAccountId, kinds: Option<Vec<String>>) {
+        let sender_id = env::predecessor_account_id();
+        let delegate_id: AccountId = delegate_id.into();
+        assert_ne!(sender_id, delegate_id, "ERR_SELF_DELEGATION");
+        let policy = self.policy.get().unwrap().to_policy();
+        let user = self.internal_user_info();
+        assert!(user.amount > 0 || policy.can_vote(user), "ERR_NO_VOTING_POWER");
+        let initial_storage = env::storage_usage();
+        self.internal_remove_vote_delegation(&sender_id);
+        let mut delegators = self.vote_delegators.get(&delegate_id).unwrap_or_default();
+        assert!(delegators.len() < MAX_VOTE_DELEGATORS, "ERR_TOO_MANY_DELEGATORS");
+        delegators.push(sender_id.clone());
+        self.vote_delegators.insert(&delegate_id, &delegators);
+        self.vote_delegations
+            .insert(&sender_id, &VoteDelegation { delegate_id, kinds });
+        self.internal_settle_storage(&sender_id, initial_storage);
+    }
+
+    /// Revoke the caller's vote delegation. Votes already cast by the delegate keep
+    /// the caller's weight unless the caller votes directly. The released storage is
+    /// refunded to the caller.
+    pub fn revoke_vote_delegation(&mut self) {
+        let sender_id = env::predecessor_account_id();
+        assert!(
+            self.vote_delegations.get(&sender_id).is_some(),
+            "ERR_NO_VOTE_DELEGATION"
+        );
+        let initial_storage = env::storage_usage();
+        self.internal_remove_vote_delegation(&sender_id);
+        self.internal_settle_storage(&sender_id, initial_storage);
+    }
+
+    /// Remove a member's delegation to the caller, which frees their slot among the
+    /// caller's delegators. The released storage is refunded to the delegator.
+    pub fn remove_vote_delegator(&mut self, delegator_id: ValidAccountId) {
+        let delegator_id: AccountId = delegator_id.into();
+        let delegation = self
+            .vote_delegations
+            .get(&delegator_id)
+            .expect("ERR_NO_VOTE_DELEGATION");
+        assert_eq!(
+            delegation.delegate_id,
+            env::predecessor_account_id(),
+            "ERR_NOT_DELEGATE"
+        );
+        let initial_storage = env::storage_usage();
+        self.internal_remove_vote_delegation(&delegator_id);
+        self.internal_settle_storage(&delegator_id, initial_storage);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, MockedBlockchain};
+    use near_sdk_sim::to_yocto;
+
+    use crate::proposals::ProposalStatus;
+    use crate::test_utils::*;
+
+    use super::*;
+
+    #[test]
+    fn test_vote_delegation() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = new_contract(
+            &mut context,
+            VersionedPolicy::Default((1..6).map(|i| accounts(i).into()).collect()),
+        );
+        let locked = contract.locked_amount;
+        testing_env!(context.attached_deposit(to_yocto("0.01")).build());
+        for i in 2..4 {
+            testing_env!(context.predecessor_account_id(accounts(i)).build());
+            contract.delegate_vote(accounts(1), None);
+        }
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        contract.delegate_vote(accounts(1), Some(vec!["other".to_string()]));
+        assert_eq!(contract.get_vote_delegators(accounts(1)).len(), 3);
+        assert!(contract.locked_amount > locked);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        let id = create_proposal(&mut context, &mut contract);
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.reject(id);
+
+        // accounts(3) already voted and accounts(4) delegated another kind.
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.approve(id, 0);
+        let proposal = contract.get_proposal(id).proposal;
+        assert_eq!(proposal.approve_count[0], 2);
+        assert_eq!(proposal.votes[&accounts(1).to_string()].delegated.len(), 1);
+        assert_eq!(proposal.delegated_votes[&accounts(2).to_string()], accounts(1).to_string());
+
+        // voting directly overrides the delegation.
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.reject(id);
+        let proposal = contract.get_proposal(id).proposal;
+        assert_eq!(proposal.approve_count[0], 1);
+        assert_eq!(proposal.reject_count, 2);
+        assert_eq!(proposal.status, ProposalStatus::InProgress);
+        assert!(proposal.delegated_votes.is_empty());
+
+        testing_env!(context.predecessor_account_id(accounts(2)).attached_deposit(0).build());
+        let locked = contract.locked_amount;
+        contract.revoke_vote_delegation();
+        assert_eq!(contract.get_vote_delegation(accounts(2)), None);
+        assert_eq!(contract.get_vote_delegators(accounts(1)).len(), 2);
+        assert!(contract.locked_amount < locked);
+
+        // the delegate can free the slot of a delegator.
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        let locked = contract.locked_amount;
+        contract.remove_vote_delegator(accounts(4));
+        assert_eq!(contract.get_vote_delegation(accounts(4)), None);
+        assert_eq!(contract.get_vote_delegators(accounts(1)).len(), 1);
+        assert!(contract.locked_amount < locked);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_DELEGATE")]
+    fn test_remove_vote_delegator_not_delegate() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = new_contract(
+            &mut context,
+            VersionedPolicy::Default((1..4).map(|i| accounts(i).into()).collect()),
+        );
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(to_yocto("0.01"))
+            .build());
+        contract.delegate_vote(accounts(1), None);
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(0).build());
+        contract.remove_vote_delegator(accounts(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NO_VOTING_POWER")]
+    fn test_vote_delegation_without_voting_power() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = new_contract(
+            &mut context,
+            VersionedPolicy::Default(vec![accounts(1).into()]),
+        );
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(to_yocto("0.01"))
+            .build());
+        contract.delegate_vote(accounts(1), None);
+    }
+}
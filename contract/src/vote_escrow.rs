@@ -0,0 +1,372 @@
+use std::ops::Bound;
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::Vector;
+use near_sdk::json_types::{WrappedDuration, WrappedTimestamp, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId, Balance};
+
+use crate::policy::{mul_div, WeightKind};
+use crate::*;
+
+/// Delegated tokens a member locked until `end`, as set at `since`.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VoteLock {
+    /// Time from which the lock has this amount and end.
+    pub since: WrappedTimestamp,
+    /// Amount of delegated tokens locked.
+    pub amount: U128,
+    /// Time until which the tokens are locked.
+    pub end: WrappedTimestamp,
+}
+
+impl VoteLock {
+    /// Returns the voting power of the lock at the given time, which decreases
+    /// linearly with the remaining lock time.
+    pub fn power(&self, max_lock_duration: u64, timestamp: u64) -> Balance {
+        if timestamp >= self.end.0 || max_lock_duration == 0 {
+            return 0;
+        }
+        let remaining = std::cmp::min(self.end.0 - timestamp, max_lock_duration);
+        // Scale the amount down to 64 bits to avoid overflows.
+        let shift = (128 - self.amount.0.leading_zeros()).saturating_sub(64);
+        ((self.amount.0 >> shift) * remaining as u128 / max_lock_duration as u128) << shift
+    }
+}
+
+/// Total voting power of the vote escrow locks at `timestamp`.
+#[derive(BorshSerialize, BorshDeserialize, Clone)]
+pub struct VoteEscrowCheckpoint {
+    pub timestamp: u64,
+    /// Total power of the locks that haven't ended.
+    pub power: Balance,
+    /// Total amount of the locks that haven't ended. The power decreases by this
+    /// much over a max lock duration.
+    pub slope: Balance,
+}
+
+impl VoteEscrowCheckpoint {
+    /// Returns the checkpoint moved on to `timestamp`, given the ends and amounts of
+    /// the locks that end in between in order.
+    fn advance(
+        mut self,
+        ends: impl Iterator<Item = (u64, Balance)>,
+        timestamp: u64,
+        max_lock_duration: u64,
+    ) -> Self {
+        for (end, amount) in ends.chain(std::iter::once((timestamp, 0))) {
+            let decay = if max_lock_duration == 0 {
+                self.power
+            } else {
+                mul_div(
+                    self.slope,
+                    (end - self.timestamp) as u128,
+                    max_lock_duration as u128,
+                )
+            };
+            self.power = self.power.saturating_sub(decay);
+            self.slope -= amount;
+            if self.slope == 0 {
+                // Leave no rounding leftovers once all locks ended.
+                self.power = 0;
+            }
+            self.timestamp = end;
+        }
+        self
+    }
+}
+
+impl Contract {
+    /// Returns the lock of the user as it was at the given time.
+    pub fn get_vote_lock_at(&self, account_id: &AccountId, timestamp: u64) -> Option<VoteLock> {
+        let locks = self.vote_locks.get(account_id)?;
+        // Find the first lock set after the timestamp.
+        let (mut low, mut high) = (0, locks.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            if locks.get(mid).unwrap().since.0 <= timestamp {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        if low == 0 {
+            None
+        } else {
+            locks.get(low - 1)
+        }
+    }
+
+    /// Returns the total voting power of the vote escrow locks at the given time,
+    /// leaving out the locks set at that time.
+    pub fn get_total_vote_power_at(&self, max_lock_duration: u64, timestamp: u64) -> Balance {
+        // Find the first checkpoint at or after the timestamp.
+        let (mut low, mut high) = (0, self.vote_escrow_checkpoints.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            if self.vote_escrow_checkpoints.get(mid).unwrap().timestamp < timestamp {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        if low == 0 {
+            return 0;
+        }
+        let checkpoint = self.vote_escrow_checkpoints.get(low - 1).unwrap();
+        let ends = self.lock_expiries.range((
+            Bound::Excluded(checkpoint.timestamp),
+            Bound::Included(timestamp),
+        ));
+        checkpoint.advance(ends, timestamp, max_lock_duration).power
+    }
+
+    /// Returns the amount of the user's delegated tokens that are locked at the moment.
+    pub fn get_locked_delegation(&self, account_id: &AccountId) -> Balance {
+        match self.get_vote_lock_at(account_id, env::block_timestamp()) {
+            Some(lock) if lock.end.0 > env::block_timestamp() => lock.amount.0,
+            _ => 0,
+        }
+    }
+
+    /// Returns the voting power of the user at the given time for the given vote policy.
+    /// Under vote escrow this is the power of the user's lock, otherwise the delegated amount.
+    /// Changes made at that time are left out.
+    pub(crate) fn internal_vote_power(
+        &self,
+        policy: &Policy,
+        vote_policy: &VotePolicy,
+        account_id: &AccountId,
+        timestamp: u64,
+    ) -> Balance {
+        match vote_policy.weight_kind {
+            WeightKind::VoteEscrow => self
+                .get_vote_lock_at(account_id, timestamp.saturating_sub(1))
+                .map(|lock| lock.power(policy.max_lock_duration.0, timestamp))
+                .unwrap_or_default(),
+            _ => self.get_user_weight_at(account_id, timestamp),
+        }
+    }
+
+    /// Returns the total voting power at the given time that the thresholds of the vote
+    /// policy are computed over: the power of all locks under vote escrow, the sum of the
+    /// square roots of the delegations under quadratic voting, otherwise the delegated amount.
+    pub(crate) fn internal_total_supply(
+        &self,
+        policy: &Policy,
+        vote_policy: &VotePolicy,
+        timestamp: u64,
+    ) -> Balance {
+        match vote_policy.weight_kind {
+            WeightKind::VoteEscrow => {
+                self.get_total_vote_power_at(policy.max_lock_duration.0, timestamp)
+            }
+            WeightKind::Quadratic => {
+                assert!(
+                    self.unmigrated_delegation_amount == 0
+                        && !matches!(self.delegations_migrated_at, Some(t) if t >= timestamp),
+                    "ERR_DELEGATIONS_NOT_MIGRATED"
+                );
+                self.get_total_quadratic_weight_at(timestamp)
+            }
+            _ => self.get_total_delegation_at(timestamp),
+        }
+    }
+
+    /// Whether the given policy keeps the longest lock duration of the current one.
+    /// The duration can't change once anything was locked, since the recorded voting
+    /// power of the locks decays over it.
+    pub(crate) fn internal_keeps_lock_duration(&self, policy: &Policy, new_policy: &VersionedPolicy) -> bool {
+        self.vote_escrow_checkpoints.is_empty()
+            || new_policy.clone().upgrade().to_policy().max_lock_duration == policy.max_lock_duration
+    }
+
+    /// Replaces the lock of the user with `lock` as of now, and records the new total
+    /// voting power of the locks.
+    fn internal_set_vote_lock(&mut self, account_id: &AccountId, lock: VoteLock, max_lock_duration: u64) {
+        let now = env::block_timestamp();
+        let mut checkpoint = match self.vote_escrow_checkpoints.len() {
+            0 => VoteEscrowCheckpoint { timestamp: now, power: 0, slope: 0 },
+            len => {
+                let last = self.vote_escrow_checkpoints.get(len - 1).unwrap();
+                let ends = self
+                    .lock_expiries
+                    .range((Bound::Excluded(last.timestamp), Bound::Included(now)));
+                last.advance(ends, now, max_lock_duration)
+            }
+        };
+        match self.get_vote_lock_at(account_id, now) {
+            Some(previous) if previous.end.0 > now => {
+                checkpoint.power = checkpoint
+                    .power
+                    .saturating_sub(previous.power(max_lock_duration, now));
+                checkpoint.slope -= previous.amount.0;
+                let ending = self.lock_expiries.get(&previous.end.0).unwrap() - previous.amount.0;
+                if ending == 0 {
+                    self.lock_expiries.remove(&previous.end.0);
+                } else {
+                    self.lock_expiries.insert(&previous.end.0, &ending);
+                }
+            }
+            _ => {}
+        }
+        checkpoint.power += lock.power(max_lock_duration, now);
+        checkpoint.slope += lock.amount.0;
+        let ending = self.lock_expiries.get(&lock.end.0).unwrap_or_default() + lock.amount.0;
+        self.lock_expiries.insert(&lock.end.0, &ending);
+
+        let len = self.vote_escrow_checkpoints.len();
+        if len > 0 && self.vote_escrow_checkpoints.get(len - 1).unwrap().timestamp == now {
+            self.vote_escrow_checkpoints.replace(len - 1, &checkpoint);
+        } else {
+            self.vote_escrow_checkpoints.push(&checkpoint);
+        }
+
+        let mut locks = self.vote_locks.get(account_id).unwrap_or_else(|| {
+            Vector::new(StorageKeys::AccountVoteLocks {
+                account_id: account_id.clone(),
+            })
+        });
+        match locks.len() {
+            len if len > 0 && locks.get(len - 1).unwrap().since.0 == now => {
+                locks.replace(len - 1, &lock);
+            }
+            _ => locks.push(&lock),
+        }
+        self.vote_locks.insert(account_id, &locks);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Lock `amount` more of the caller's delegated tokens for vote escrow and extend
+    /// the lock to end no earlier than `duration` from now. Locked tokens can't be
+    /// undelegated until the lock ends.
+    pub fn lock(&mut self, amount: U128, duration: WrappedDuration) {
+        let policy = self.policy.get().unwrap().to_policy();
+        assert!(
+            duration.0 <= policy.max_lock_duration.0,
+            "ERR_LOCK_TOO_LONG"
+        );
+        let sender_id = env::predecessor_account_id();
+        let now = env::block_timestamp();
+        let amount = self.get_locked_delegation(&sender_id) + amount.0;
+        assert!(
+            amount <= self.get_user_weight(&sender_id),
+            "ERR_NOT_ENOUGH_DELEGATED"
+        );
+        let end = match self.get_vote_lock_at(&sender_id, now) {
+            Some(lock) if lock.end.0 > now => std::cmp::max(lock.end.0, now + duration.0),
+            _ => now + duration.0,
+        };
+        assert!(end > now, "ERR_ZERO_LOCK");
+        let lock = VoteLock {
+            since: WrappedTimestamp::from(now),
+            amount: U128(amount),
+            end: WrappedTimestamp::from(end),
+        };
+        self.internal_set_vote_lock(&sender_id, lock, policy.max_lock_duration.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, MockedBlockchain};
+    use near_sdk_sim::to_yocto;
+
+    use crate::proposals::ProposalStatus;
+    use crate::test_utils::*;
+
+    use super::*;
+
+    fn vote_escrow_contract(context: &mut VMContextBuilder) -> Contract {
+        let mut policy = VersionedPolicy::Default(vec![accounts(1).into(), accounts(2).into()])
+            .upgrade();
+        policy.to_policy_mut().default_vote_policy.weight_kind = WeightKind::VoteEscrow;
+        policy.to_policy_mut().max_lock_duration = WrappedDuration::from(100);
+        let mut contract = new_contract(context, policy);
+        contract.staking_id = Some(accounts(0).into());
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        for i in 1..3 {
+            contract.delegations.insert(&accounts(i).into(), &0);
+            contract.delegate(&accounts(i).into(), U128(100));
+        }
+        contract
+    }
+
+    #[test]
+    fn test_vote_escrow() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = vote_escrow_contract(&mut context);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.lock(U128(100), WrappedDuration::from(100));
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.lock(U128(100), WrappedDuration::from(50));
+
+        // the power decreases with the remaining lock time, the threshold is half
+        // of the total power of 130 at submission.
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .block_timestamp(10)
+            .build());
+        let id = create_proposal(&mut context, &mut contract);
+        assert_eq!(contract.get_total_vote_power_at(100, 10), 130);
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.approve(id, 0);
+        let proposal = contract.get_proposal(id).proposal;
+        assert_eq!(proposal.approve_count[0], 40);
+        assert_eq!(proposal.status, ProposalStatus::InProgress);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.approve(id, 0);
+        let proposal = contract.get_proposal(id).proposal;
+        assert_eq!(proposal.approve_count[0], 130);
+        assert_eq!(proposal.status, ProposalStatus::Approved{ version: 0 });
+
+        // locks that ended leave the total, and a lock set in the same block as a
+        // snapshot is left out of it.
+        testing_env!(context.block_timestamp(60).build());
+        contract.lock(U128(0), WrappedDuration::from(100));
+        assert_eq!(contract.get_total_vote_power_at(100, 60), 40);
+        assert_eq!(contract.get_total_vote_power_at(100, 61), 99);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_LOCKED")]
+    fn test_vote_escrow_undelegate_locked() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = vote_escrow_contract(&mut context);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.lock(U128(60), WrappedDuration::from(100));
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(10)
+            .build());
+        contract.undelegate(&accounts(1).into(), U128(40));
+        contract.undelegate(&accounts(1).into(), U128(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_LOCK_DURATION_FIXED")]
+    fn test_vote_escrow_lock_duration_fixed() {
+        let mut context = VMContextBuilder::new();
+        let mut contract = vote_escrow_contract(&mut context);
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(to_yocto("1"))
+            .build());
+        let mut policy = contract.get_policy();
+        policy.max_lock_duration = WrappedDuration::from(200);
+        // the duration can change until anything is locked.
+        contract.propose(
+            "change policy".to_string(),
+            vec![Instruction::ChangePolicy { policy: VersionedPolicy::Current(policy.clone()) }],
+        );
+        contract.lock(U128(100), WrappedDuration::from(100));
+        contract.propose(
+            "change policy".to_string(),
+            vec![Instruction::ChangePolicy { policy: VersionedPolicy::Current(policy) }],
+        );
+    }
+}
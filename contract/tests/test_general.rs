@@ -45,6 +45,9 @@ fn test_multi_council() {
         proposal_period: WrappedDuration::from(1_000_000_000 * 60 * 60 * 24 * 7),
         bounty_bond: U128(10u128.pow(24)),
         bounty_forgiveness_period: WrappedDuration::from(1_000_000_000 * 60 * 60 * 24),
+        max_lock_duration: WrappedDuration::from(1_000_000_000 * 60 * 60 * 24 * 365 * 4),
+        templates: vec![],
+        sub_daos: vec![],
     };
     add_proposal(
         &root,